@@ -0,0 +1,234 @@
+use json::JsonValue;
+use smcore::{ISmCoreSupport, smu};
+use smdton::{SmDtonBuilder, SmDtonReader};
+use smloadwasm::EngineConfig;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::exit;
+use std::time::SystemTime;
+
+const EXIT_USAGE: i32 = 1;
+const EXIT_LOAD: i32 = 2;
+const EXIT_CALL: i32 = 3;
+
+const HELP: &str = "usage:
+  smloadwasm run <wasm> <pagenum> <usage> [json]
+  smloadwasm catalog <wasm> [pagenum] [--json]
+  smloadwasm compile <wasm> [-o <cwasm>] [--target <triple>]
+engine flags, the same ones must be given when loading a .cwasm:
+  --fuel --epoch";
+
+// stdout carries only the result, every log line goes to stderr
+struct StderrLog {}
+
+impl ISmCoreSupport for StderrLog {
+    fn sm_log(&self, txt: &str) {
+        eprintln!("{}", txt);
+    }
+
+    fn get_current_ms(&self) -> u128 {
+        return SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+    }
+}
+
+fn main() {
+    smu.set_wasm(0, Some(Box::new(StderrLog {})));
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (egc, args) = engine_flags(&args);
+    let code = match args.first().map(|s| s.as_str()) {
+        Some("run") => run(&args[1..], &egc),
+        Some("catalog") => catalog(&args[1..], &egc),
+        Some("compile") => compile(&args[1..], &egc),
+        Some("-h") | Some("--help") => {
+            println!("{}", HELP);
+            0
+        }
+        _ => {
+            eprintln!("{}", HELP);
+            EXIT_USAGE
+        }
+    };
+    exit(code);
+}
+
+// takes the engine flags out of the arguments
+fn engine_flags(args: &[String]) -> (EngineConfig, Vec<String>) {
+    let mut egc = EngineConfig::default();
+    let mut rest: Vec<String> = Vec::new();
+    for a in args {
+        match a.as_str() {
+            "--fuel" => egc.fuel = true,
+            "--epoch" => egc.epoch = true,
+            _ => rest.push(a.clone()),
+        }
+    }
+    return (egc, rest);
+}
+
+fn run(args: &[String], egc: &EngineConfig) -> i32 {
+    if args.len() < 3 || args.len() > 4 {
+        eprintln!("{}", HELP);
+        return EXIT_USAGE;
+    }
+    let wasm_path = &args[0];
+    let pagenum: i32 = match args[1].parse() {
+        Ok(n) => n,
+        Err(_) => {
+            eprintln!("--- bad pagenum --- {}", args[1]);
+            return EXIT_USAGE;
+        }
+    };
+    let usage = &args[2];
+    let mut input = match args.get(3) {
+        Some(txt) => match json::parse(txt) {
+            Ok(jsn) => jsn,
+            Err(e) => {
+                eprintln!("--- bad json input --- {}", e);
+                return EXIT_USAGE;
+            }
+        },
+        None => JsonValue::new_object(),
+    };
+    if !input.is_object() {
+        eprintln!("--- json input must be an object ---");
+        return EXIT_USAGE;
+    }
+    input["$usage"] = usage.as_str().into();
+
+    smloadwasm::init_with(egc);
+    if let Err(e) = smloadwasm::load_wasm(wasm_path, pagenum) {
+        eprintln!("--- load failed --- {}", e);
+        return EXIT_LOAD;
+    }
+
+    let smb = SmDtonBuilder::new_from_json(&input).build();
+    let out = smloadwasm::call_wasm(usage, &smb);
+    let rd = SmDtonReader::new(out.get_buffer());
+    match rd.to_json(1) {
+        Some(jsn) => {
+            println!("{}", jsn.pretty(2));
+            if jsn.has_key("$error") {
+                return EXIT_CALL;
+            }
+            return 0;
+        }
+        None => {
+            eprintln!("--- call returned no json --- {}", usage);
+            return EXIT_CALL;
+        }
+    }
+}
+
+fn catalog(args: &[String], egc: &EngineConfig) -> i32 {
+    let as_json = args.iter().any(|a| a == "--json");
+    let rest: Vec<&String> = args.iter().filter(|a| *a != "--json").collect();
+    if rest.is_empty() || rest.len() > 2 {
+        eprintln!("{}", HELP);
+        return EXIT_USAGE;
+    }
+    let wasm_path = rest[0];
+    let pagenum: i32 = match rest.get(1).map(|s| s.parse()) {
+        None => 1,
+        Some(Ok(n)) => n,
+        Some(Err(_)) => {
+            eprintln!("--- bad pagenum --- {}", rest[1]);
+            return EXIT_USAGE;
+        }
+    };
+
+    smloadwasm::init_with(egc);
+    let entries = match smloadwasm::get_catalog(wasm_path, pagenum) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("--- load failed --- {}", e);
+            return EXIT_LOAD;
+        }
+    };
+
+    if as_json {
+        let mut arr = JsonValue::new_array();
+        for x in &entries {
+            let mut itm = JsonValue::new_object();
+            itm["usage"] = x.usage.as_str().into();
+            itm["meta"] = x.meta.clone();
+            let _ = arr.push(itm);
+        }
+        println!("{}", arr.pretty(2));
+        return 0;
+    }
+
+    let width = entries
+        .iter()
+        .map(|x| x.usage.len())
+        .max()
+        .unwrap_or(0)
+        .max("USAGE".len());
+    println!("{:<width$}  META", "USAGE", width = width);
+    for x in &entries {
+        println!("{:<width$}  {}", x.usage, x.meta.dump(), width = width);
+    }
+    return 0;
+}
+
+fn compile(args: &[String], egc: &EngineConfig) -> i32 {
+    let mut wasm_path: Option<&String> = None;
+    let mut out: Option<&String> = None;
+    let mut target: Option<&String> = None;
+    let mut it = args.iter();
+    while let Some(a) = it.next() {
+        match a.as_str() {
+            "-o" | "--target" => {
+                let v = match it.next() {
+                    Some(v) => v,
+                    None => {
+                        eprintln!("{}", HELP);
+                        return EXIT_USAGE;
+                    }
+                };
+                if a == "-o" {
+                    out = Some(v);
+                } else {
+                    target = Some(v);
+                }
+            }
+            _ if wasm_path.is_none() => wasm_path = Some(a),
+            _ => {
+                eprintln!("{}", HELP);
+                return EXIT_USAGE;
+            }
+        }
+    }
+    let wasm_path = match wasm_path {
+        Some(p) => p,
+        None => {
+            eprintln!("{}", HELP);
+            return EXIT_USAGE;
+        }
+    };
+    let out = match out {
+        Some(o) => o.clone(),
+        None => Path::new(wasm_path)
+            .with_extension("cwasm")
+            .to_string_lossy()
+            .to_string(),
+    };
+
+    smloadwasm::init_with(egc);
+    let data = match smloadwasm::compile_wasm(wasm_path, target.map(|t| t.as_str())) {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("--- compile failed --- {}", e);
+            return EXIT_LOAD;
+        }
+    };
+    if let Err(e) = fs::write(&out, &data) {
+        eprintln!("--- cannot write --- {} --- {}", out, e);
+        return EXIT_LOAD;
+    }
+    println!("{}", out);
+    return 0;
+}
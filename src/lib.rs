@@ -1,17 +1,132 @@
 mod smwasm;
 mod wasm;
+mod wasm_args;
+mod wasm_cache;
+mod wasm_config;
+mod wasm_det;
+mod wasm_emsc;
+mod wasm_error;
 mod wasm_import;
+mod wasm_limit;
+mod wasm_output;
+mod wasm_pool;
+mod wasm_stat;
 mod wasm_store;
 mod wasm_util;
+mod wasm_valid;
+#[cfg(feature = "wasi")]
+mod wasm_wasi;
 
+use json::JsonValue;
 use smcore::smu;
+use smdton::SmDtonBuffer;
 
+pub use smwasm::ServiceEntry;
+pub use wasm_config::{CallOption, Deterministic, EngineConfig, Preopen, WasmConfig};
+pub use wasm_error::LoadError;
+pub use wasm_valid::{ExportCheck, WasmReport};
+
+// keeps a log support the host set before, smcore's own one prints to stdout
 pub fn init() -> bool {
-    smu.set_wasm(0, None);
+    if !smu.has_support() {
+        smu.set_wasm(0, None);
+    }
     smwasm::_sm_init();
     return true;
 }
 
-pub fn load_wasm(_wp: &str, pagenum: i32) {
-    smwasm::load_wasm(_wp, pagenum);
+// must run before the first module is loaded, false when the engine
+// was already built and egc is not applied
+pub fn init_with(egc: &EngineConfig) -> bool {
+    let applied = wasm_util::set_engine(egc);
+    init();
+    return applied;
+}
+
+pub fn load_wasm(_wp: &str, pagenum: i32) -> Result<(), LoadError> {
+    return smwasm::load_wasm(_wp, &WasmConfig::new(pagenum));
+}
+
+pub fn load_wasm_with(_wp: &str, cfg: &WasmConfig) -> Result<(), LoadError> {
+    return smwasm::load_wasm(_wp, cfg);
+}
+
+pub fn load_wasm_bytes(name: &str, bytes: &[u8], pagenum: i32) -> Result<(), LoadError> {
+    return smwasm::load_wasm_bytes(name, bytes, &WasmConfig::new(pagenum));
+}
+
+pub fn load_wasm_bytes_with(name: &str, bytes: &[u8], cfg: &WasmConfig) -> Result<(), LoadError> {
+    return smwasm::load_wasm_bytes(name, bytes, cfg);
+}
+
+pub fn load_wasm_wat(name: &str, text: &str, pagenum: i32) -> Result<(), LoadError> {
+    return smwasm::load_wasm_wat(name, text, &WasmConfig::new(pagenum));
+}
+
+pub fn load_wasm_wat_with(name: &str, text: &str, cfg: &WasmConfig) -> Result<(), LoadError> {
+    return smwasm::load_wasm_wat(name, text, cfg);
+}
+
+// InMemory for a module loaded from bytes or wat, reload those from new bytes or wat
+pub fn reload_wasm(_wp: &str) -> Result<(), LoadError> {
+    return smwasm::reload_wasm(_wp);
+}
+
+pub fn reload_wasm_bytes(name: &str, bytes: &[u8]) -> Result<(), LoadError> {
+    return smwasm::reload_wasm_bytes(name, bytes);
+}
+
+pub fn reload_wasm_wat(name: &str, text: &str) -> Result<(), LoadError> {
+    return smwasm::reload_wasm_wat(name, text);
+}
+
+pub fn unload_wasm(_wp: &str) -> bool {
+    return smwasm::unload_wasm(_wp);
+}
+
+pub fn call_wasm(usage: &str, _input: &SmDtonBuffer) -> SmDtonBuffer {
+    return smwasm::call_usage(usage, _input, &CallOption::default());
+}
+
+pub fn call_wasm_with(usage: &str, _input: &SmDtonBuffer, opt: &CallOption) -> SmDtonBuffer {
+    return smwasm::call_usage(usage, _input, opt);
+}
+
+pub fn get_catalog(_wp: &str, pagenum: i32) -> Result<Vec<ServiceEntry>, LoadError> {
+    return smwasm::get_catalog(_wp, &WasmConfig::new(pagenum));
+}
+
+pub fn get_catalog_with(_wp: &str, cfg: &WasmConfig) -> Result<Vec<ServiceEntry>, LoadError> {
+    return smwasm::get_catalog(_wp, cfg);
+}
+
+// same engine settings as the running engine
+pub fn compile_wasm(_wp: &str, target: Option<&str>) -> Result<Vec<u8>, LoadError> {
+    return wasm_util::precompile(_wp, target);
+}
+
+// checks the plugin abi and imports without instantiating
+pub fn validate_wasm(_wp: &str) -> Result<WasmReport, LoadError> {
+    return wasm_valid::validate_wasm(_wp, &WasmConfig::default());
+}
+
+pub fn validate_wasm_with(_wp: &str, cfg: &WasmConfig) -> Result<WasmReport, LoadError> {
+    return wasm_valid::validate_wasm(_wp, cfg);
+}
+
+// virtual clock of a module loaded with WasmConfig::deterministic
+pub fn set_clock(_wp: &str, ms: u64) -> bool {
+    return wasm_det::set_clock(_wp, ms);
+}
+
+pub fn advance_clock(_wp: &str, ms: u64) -> bool {
+    return wasm_det::advance_clock(_wp, ms);
+}
+
+pub fn get_clock(_wp: &str) -> Option<u64> {
+    return wasm_det::read_clock(_wp);
+}
+
+pub fn get_stats() -> JsonValue {
+    return wasm_stat::get_stats();
 }
@@ -1,95 +1,380 @@
-use std::collections::HashMap;
-use std::sync::RwLock;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
 
 use json::JsonValue;
 use lazy_static::lazy_static;
 
 use smcore::{smh, smu};
 
-use crate::wasm::{WS_ENV, WS_INA, WS_INM};
+use crate::wasm::{WS_ENV, WS_INM, WasmTarget};
+use crate::wasm_config::{CallOption, WasmConfig};
+use crate::wasm_det;
+use crate::wasm_error::{CallError, ERROR, LoadError};
+use crate::wasm_util::WS_UTL;
 use smdton::{SmDtonBuffer, SmDtonMap, SmDtonReader};
+use wasmtime::Module;
 
 lazy_static! {
     // $usage to sn
     pub static ref WS_NAM: RwLock<HashMap<String, i32>> = RwLock::new(HashMap::new());
     pub static ref JS_EMP: JsonValue = json::parse("{}").unwrap();
+    // names loaded from bytes or wat, reloaded only from new bytes or wat
+    pub static ref WS_MEM: RwLock<HashSet<String>> = RwLock::new(HashSet::new());
+    // one load, reload or unload of a path at a time
+    pub static ref WS_PLK: Mutex<HashMap<String, Arc<Mutex<()>>>> = Mutex::new(HashMap::new());
 }
 
 const SM_PREFIX: &str = "smwasm";
 const SMKER_GET_ALL: &str = "smker.get.all";
 const USAGE: &str = "$usage";
 
-pub fn load_wasm(_wp: &str, pagenum: i32) -> bool {
-    if !WS_ENV.check_instance(&_wp, pagenum) {
-        return false;
+fn _get_lock(_wp: &str) -> Arc<Mutex<()>> {
+    let mut map = WS_PLK.lock().unwrap();
+    return map.entry(_wp.to_string()).or_default().clone();
+}
+
+fn _lock(plk: &Mutex<()>) -> MutexGuard<'_, ()> {
+    return match plk.lock() {
+        Ok(g) => g,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+}
+
+pub fn load_wasm(_wp: &str, cfg: &WasmConfig) -> Result<(), LoadError> {
+    let plk = _get_lock(_wp);
+    let _g = _lock(&plk);
+    return _load_wasm(_wp, cfg);
+}
+
+fn _load_wasm(_wp: &str, cfg: &WasmConfig) -> Result<(), LoadError> {
+    let fresh = !_is_loaded(_wp);
+    let r = WS_ENV
+        .check_instance(&_wp, cfg)
+        .and_then(|sn| _get_catalog(_wp, sn).map(|jsn| (sn, jsn)));
+    let (sn, jsn) = match r {
+        Ok(v) => v,
+        Err(e) => {
+            // nothing of a failed first load stays behind, the next load starts over
+            if fresh {
+                WS_ENV.drop_instance(_wp);
+                wasm_det::drop_clock(_wp);
+            }
+            return Err(e);
+        }
+    };
+    {
+        let mut map = WS_NAM.write().unwrap();
+        _register(sn, &jsn, &mut map);
+    }
+    return Ok(());
+}
+
+pub fn load_wasm_bytes(name: &str, bytes: &[u8], cfg: &WasmConfig) -> Result<(), LoadError> {
+    let plk = _get_lock(name);
+    let _g = _lock(&plk);
+    if !_is_loaded(name) {
+        let _module = WS_UTL.load_bytes(name, bytes)?;
+        WS_UTL.add_module(name, _module);
+        _load_wasm(name, cfg)?;
+        _set_memory(name, true);
+        return Ok(());
+    }
+    return _load_wasm(name, cfg);
+}
+
+pub fn load_wasm_wat(name: &str, text: &str, cfg: &WasmConfig) -> Result<(), LoadError> {
+    let plk = _get_lock(name);
+    let _g = _lock(&plk);
+    if !_is_loaded(name) {
+        let _module = WS_UTL.load_wat(name, text)?;
+        WS_UTL.add_module(name, _module);
+        _load_wasm(name, cfg)?;
+        _set_memory(name, true);
+        return Ok(());
+    }
+    return _load_wasm(name, cfg);
+}
+
+fn _is_loaded(name: &str) -> bool {
+    let map = WS_INM.read().unwrap();
+    return map.contains_key(name);
+}
+
+fn _is_memory(name: &str) -> bool {
+    let mem = WS_MEM.read().unwrap();
+    return mem.contains(name);
+}
+
+fn _set_memory(name: &str, on: bool) {
+    let mut mem = WS_MEM.write().unwrap();
+    if on {
+        mem.insert(name.to_string());
+    } else {
+        mem.remove(name);
     }
-    let sn: usize;
+}
+
+pub fn reload_wasm(_wp: &str) -> Result<(), LoadError> {
+    let plk = _get_lock(_wp);
+    let _g = _lock(&plk);
+    if !_is_loaded(_wp) {
+        return Err(LoadError::NotLoaded(_wp.to_string()));
+    }
+    // a file of the same name is not what was loaded
+    if _is_memory(_wp) {
+        return Err(LoadError::InMemory(_wp.to_string()));
+    }
+    let _module = WS_UTL.load(_wp)?;
+    return _reload(_wp, _module);
+}
+
+pub fn reload_wasm_bytes(name: &str, bytes: &[u8]) -> Result<(), LoadError> {
+    let plk = _get_lock(name);
+    let _g = _lock(&plk);
+    if !_is_loaded(name) {
+        return Err(LoadError::NotLoaded(name.to_string()));
+    }
+    let _module = WS_UTL.load_bytes(name, bytes)?;
+    _reload(name, _module)?;
+    _set_memory(name, true);
+    return Ok(());
+}
+
+pub fn reload_wasm_wat(name: &str, text: &str) -> Result<(), LoadError> {
+    let plk = _get_lock(name);
+    let _g = _lock(&plk);
+    if !_is_loaded(name) {
+        return Err(LoadError::NotLoaded(name.to_string()));
+    }
+    let _module = WS_UTL.load_wat(name, text)?;
+    _reload(name, _module)?;
+    _set_memory(name, true);
+    return Ok(());
+}
+
+// under the path lock, old is the slot this reload takes the usages from
+fn _reload(_wp: &str, _module: Module) -> Result<(), LoadError> {
+    let old;
+    {
+        let map = WS_INM.read().unwrap();
+        match map.get(_wp) {
+            Some(v) => old = *v as usize,
+            None => return Err(LoadError::NotLoaded(_wp.to_string())),
+        }
+    }
+    let cfg = match WS_ENV.get_config(_wp) {
+        Some(cfg) => cfg,
+        None => return Err(LoadError::NotLoaded(_wp.to_string())),
+    };
+
+    let sn = WS_ENV.create_instance(_wp, &cfg, &_module)?;
+    let jsn = match _get_catalog(_wp, sn) {
+        Ok(jsn) => jsn,
+        Err(e) => {
+            WS_ENV.drop_slot(sn);
+            return Err(e);
+        }
+    };
+
+    // repoints every usage at once, drop_slot then drains the calls still on old
+    let dropped;
+    {
+        let mut map = WS_NAM.write().unwrap();
+        dropped = _take_usages(old as i32, &mut map);
+        _register(sn, &jsn, &mut map);
+        WS_ENV.swap_instance(_wp, sn, _module);
+    }
+    WS_ENV.drop_slot(old);
+    let dropped: Vec<String> = dropped.into_iter().filter(|x| !jsn.has_key(x)).collect();
+    _unregister(_wp, &dropped);
+
+    smu.log(&format!(
+        "--- reload wasm --- {} --- {} -> {} ---",
+        _wp, old, sn
+    ));
+    return Ok(());
+}
+
+// one service of a module, from smker.get.all
+#[derive(Clone, Debug)]
+pub struct ServiceEntry {
+    pub usage: String,
+    pub meta: JsonValue,
+}
+
+// catalog without registering, a module not loaded yet goes to a temporary slot
+pub fn get_catalog(_wp: &str, cfg: &WasmConfig) -> Result<Vec<ServiceEntry>, LoadError> {
+    let loaded;
     {
         let map = WS_INM.read().unwrap();
-        sn = *map.get(_wp).unwrap() as usize;
+        loaded = map.get(_wp).map(|v| *v as usize);
+    }
+
+    let jsn = match loaded {
+        Some(sn) => _get_catalog(_wp, sn)?,
+        None => {
+            let _module = WS_UTL.load(_wp)?;
+            let mut one = cfg.clone();
+            one.pool = 1;
+            let sn = WS_ENV.create_instance(_wp, &one, &_module)?;
+            let ret = _get_catalog(_wp, sn);
+            WS_ENV.drop_slot(sn);
+            wasm_det::drop_clock(_wp);
+            ret?
+        }
+    };
+
+    let mut entries: Vec<ServiceEntry> = Vec::new();
+    for x in jsn.entries() {
+        if x.0 == SMKER_GET_ALL {
+            continue;
+        }
+        entries.push(ServiceEntry {
+            usage: x.0.to_string(),
+            meta: x.1.clone(),
+        });
     }
+    return Ok(entries);
+}
 
+fn _get_catalog(_wp: &str, sn: usize) -> Result<JsonValue, LoadError> {
     let mut smp = SmDtonMap::new();
     smp.add_string(USAGE, SMKER_GET_ALL);
     let smb = smp.build();
-    if let Some(a) = WS_INA.get(sn as usize) {
-        if let Some(inst) = a {
-            let ptr = inst.set_input(SMKER_GET_ALL, &smb);
-            let out_smb = inst.call(ptr);
-
-            let rd = SmDtonReader::new(out_smb.get_buffer());
-            let opall = rd.to_json(1);
-            match opall {
-                Some(jsn) => {
-                    for x in jsn.entries() {
-                        if x.0 == SMKER_GET_ALL {
-                            continue;
-                        }
-                        let mut smp = SmDtonMap::new();
-                        smp.add_string(USAGE, x.0);
-                        smp.add_from_json(x.1);
-                        smh.register(smp.build(), _sm_call_outside);
-                        {
-                            let mut map = WS_NAM.write().unwrap();
-                            map.insert(x.0.to_string(), sn as i32);
-                        }
-                    }
-                }
-                _ => {}
+    if let Some(inst) = WS_ENV.get_ina(sn) {
+        let out_smb = inst.call(SMKER_GET_ALL, &smb, &CallOption::default());
+
+        let rd = SmDtonReader::new(out_smb.get_buffer());
+        if let Some(jsn) = rd.to_json(1) {
+            if !jsn.has_key(ERROR) {
+                return Ok(jsn);
             }
         }
     }
+    return Err(LoadError::CatalogDecode(_wp.to_string()));
+}
 
-    return true;
+fn _register(sn: usize, jsn: &JsonValue, map: &mut HashMap<String, i32>) {
+    for x in jsn.entries() {
+        if x.0 == SMKER_GET_ALL {
+            continue;
+        }
+        let mut smp = SmDtonMap::new();
+        smp.add_string(USAGE, x.0);
+        smp.add_from_json(x.1);
+        smh.register(smp.build(), _sm_call_outside);
+        map.insert(x.0.to_string(), sn as i32);
+    }
 }
 
-pub fn call_wasm(sn: i32, name: &str, _input: &SmDtonBuffer) -> SmDtonBuffer {
-    if sn < 0 {
-        return SmDtonBuffer::new();
+fn _take_usages(sn: i32, map: &mut HashMap<String, i32>) -> Vec<String> {
+    let usages: Vec<String> = map
+        .iter()
+        .filter(|x| *x.1 == sn)
+        .map(|x| x.0.clone())
+        .collect();
+    for x in usages.iter() {
+        map.remove(x);
+    }
+    return usages;
+}
+
+// smh has no unregister, a dropped usage stays advertised and answers UnknownUsage
+fn _unregister(_wp: &str, usages: &[String]) {
+    for x in usages {
+        smu.log(&format!(
+            "--- smh cannot unregister --- {} --- {} ---",
+            _wp, x
+        ));
+    }
+}
+
+pub fn unload_wasm(_wp: &str) -> bool {
+    let plk = _get_lock(_wp);
+    let _g = _lock(&plk);
+    let sn;
+    {
+        let map = WS_INM.read().unwrap();
+        match map.get(_wp) {
+            Some(v) => sn = *v,
+            None => return false,
+        }
+    }
+
+    let dropped;
+    {
+        let mut map = WS_NAM.write().unwrap();
+        dropped = _take_usages(sn, &mut map);
+    }
+    _unregister(_wp, &dropped);
+
+    if WS_ENV.drop_instance(_wp).is_none() {
+        return false;
     }
+    wasm_det::drop_clock(_wp);
+    _set_memory(_wp, false);
+
+    smu.log(&format!("--- unload wasm --- {} --- {} ---", _wp, sn));
+    return true;
+}
 
-    if let Some(a) = WS_INA.get(sn as usize) {
-        if let Some(inst) = a {
-            let ptr = inst.set_input(name, _input);
-            return inst.call(ptr);
+// a call that lands on a slot a reload just dropped goes once more to the new slot
+pub fn call_usage(name: &str, _input: &SmDtonBuffer, opt: &CallOption) -> SmDtonBuffer {
+    for _ in 0..2 {
+        let (sn, tgt);
+        {
+            let map = WS_NAM.read().unwrap();
+            sn = match map.get(name) {
+                Some(v) => *v,
+                None => return CallError::UnknownUsage(name.to_string()).to_smb("", name),
+            };
+            tgt = WS_ENV.get_target(sn as usize);
+        }
+
+        // no lock held while the guest runs
+        if let Some(tgt) = tgt {
+            if let Some(ret) = _call_target(sn, &tgt, name, _input, opt) {
+                return ret;
+            }
         }
     }
 
-    return SmDtonBuffer::new();
+    return CallError::Unavailable.to_smb("", name);
+}
+
+fn _call_target(
+    sn: i32,
+    tgt: &WasmTarget,
+    name: &str,
+    _input: &SmDtonBuffer,
+    opt: &CallOption,
+) -> Option<SmDtonBuffer> {
+    match tgt {
+        WasmTarget::One(inst) => return inst.call_live(name, _input, opt),
+        WasmTarget::Pool(pool) => match pool.acquire() {
+            Some((idx, inst)) => {
+                let ret = inst.call_live(name, _input, opt);
+                pool.release(idx);
+                return ret;
+            }
+            None => {
+                smu.log(&format!("--- wasm pool busy --- {} --- {} ---", sn, name));
+                return Some(CallError::Busy.to_smb(&WS_ENV.get_path(sn as usize), name));
+            }
+        },
+    }
 }
 
 fn _sm_call_outside(_input: &SmDtonBuffer) -> SmDtonBuffer {
     let smp = SmDtonReader::new(_input.get_buffer());
-    let name = smp.get_string(1, USAGE).unwrap();
-
-    let map = WS_NAM.read().unwrap();
-    let op = map.get(name);
-    if op.is_some() {
-        let sn = *op.unwrap();
-        return call_wasm(sn, name, &_input);
+    match smp.get_string(1, USAGE) {
+        Some(name) => {
+            return call_usage(name, &_input, &CallOption::default());
+        }
+        None => {
+            return CallError::Decode(USAGE.to_string()).to_smb("", "");
+        }
     }
-
-    return SmDtonBuffer::new();
 }
 
 pub fn _sm_init() {
@@ -1,29 +1,39 @@
+use smcore::smu;
 use smdton::{SmDtonBuffer, SmDtonBuilder};
 use wasmtime::*;
 
 use lazy_static::lazy_static;
+use std::cell::Cell;
 use std::collections::HashMap;
-use std::sync::RwLock;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
 
+use crate::wasm_config::{CallOption, WasmConfig};
+use crate::wasm_error::{CallError, LoadError, get_backtrace};
 use crate::wasm_import::WasmState;
-use crate::wasm_store::WS_STO;
-use crate::wasm_util::{MAX_STORE, WS_MOD, WS_UTL};
+use crate::wasm_pool::{WS_POL, WasmPool};
+use crate::wasm_stat;
+use crate::wasm_store::WasmStoreStub;
+use crate::wasm_util::{EPOCH_NEVER, WS_MOD, WS_UTL};
+#[cfg(feature = "wasi")]
+use crate::wasm_wasi;
 
-const INA_REPEAT_VALUE: Option<WasmInstanceStub> = None;
 pub const LOAD_WAY: i32 = 0x100;
 
 lazy_static! {
     pub static ref WS_ENV: Wasm = Wasm {};
     pub static ref WS_INM: RwLock<HashMap<String, i32>> = RwLock::new(HashMap::new());
-    pub static ref WS_JSN: RwLock<[i32; MAX_STORE]> = RwLock::new([0; MAX_STORE]);
-    pub static ref WS_INA: [Option<WasmInstanceStub>; MAX_STORE] = {
-        let mut arr: [Option<WasmInstanceStub>; MAX_STORE] = [INA_REPEAT_VALUE; MAX_STORE];
-        for i in 0..MAX_STORE {
-            arr[i] = Some(WasmInstanceStub::new(i));
-        }
-        arr
-    };
+    // path to the config it was loaded with, what a reload uses again
+    pub static ref WS_CFG: RwLock<HashMap<String, WasmConfig>> = RwLock::new(HashMap::new());
+    pub static ref WS_JSN: RwLock<Vec<i32>> = RwLock::new(Vec::new());
+    // slot sn to instance, None for a free slot
+    pub static ref WS_INA: RwLock<Vec<Option<Arc<WasmInstanceStub>>>> = RwLock::new(Vec::new());
 }
+thread_local! {
+    // end of the smcall on this thread, calls nested through hostcallsm end by then too
+    static WS_END: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
 pub struct SZ {}
 
 impl SZ {
@@ -44,137 +54,477 @@ impl FL {
 pub struct Wasm {}
 
 impl Wasm {
-    pub fn check_instance(&self, wasm_path: &str, pagenum: i32) -> bool {
+    pub fn check_instance(&self, wasm_path: &str, cfg: &WasmConfig) -> Result<usize, LoadError> {
         {
             let map = WS_INM.read().unwrap();
             let itm = map.get(wasm_path);
             if itm.is_some() {
-                return *itm.unwrap() >= 0;
+                return Ok(*itm.unwrap() as usize);
             }
         }
 
-        let mut ins = WasmInstance::new(wasm_path.to_string(), pagenum);
-        ins.init();
-        let valid = ins.instance.is_some();
-        if !valid {
+        WS_UTL.check_module(wasm_path)?;
+        let _module;
+        {
+            let map = WS_MOD.read().unwrap();
+            _module = map.get(wasm_path).unwrap().clone();
+        }
+
+        let sn = self.create_instance(wasm_path, cfg, &_module)?;
+        {
+            let mut map = WS_CFG.write().unwrap();
+            map.insert(wasm_path.to_string(), cfg.clone());
+        }
+        {
             let mut map = WS_INM.write().unwrap();
-            map.insert(wasm_path.to_string(), -1);
-            return false;
+            map.insert(wasm_path.to_string(), sn as i32);
+        }
+        return Ok(sn);
+    }
+
+    pub fn create_instance(
+        &self,
+        wasm_path: &str,
+        cfg: &WasmConfig,
+        _module: &Module,
+    ) -> Result<usize, LoadError> {
+        if cfg.wasi && !cfg!(feature = "wasi") {
+            return Err(LoadError::Wasi(
+                wasm_path.to_string(),
+                "built without the wasi feature".to_string(),
+            ));
+        }
+        // wasmtime-wasi reads the host clocks and random
+        if cfg.wasi && cfg.deterministic.is_some() {
+            return Err(LoadError::Wasi(
+                wasm_path.to_string(),
+                "deterministic mode is not supported with wasi".to_string(),
+            ));
         }
 
-        let sn = ins.sn;
+        let mut sns: Vec<usize> = Vec::new();
+        for _ in 0..cfg.pool.max(1) {
+            match self._create_one(wasm_path, cfg, _module) {
+                Ok(sn) => sns.push(sn),
+                Err(e) => {
+                    for sn in sns {
+                        self.drop_slot(sn);
+                    }
+                    return Err(e);
+                }
+            }
+        }
 
-        if let Some(a) = WS_INA.get(sn) {
-            if let Some(b) = a {
+        let sn = sns[0];
+        if sns.len() > 1 {
+            let mut pol = WS_POL.write().unwrap();
+            let ins = sns.iter().filter_map(|x| self.get_ina(*x)).collect();
+            pol.insert(sn, Arc::new(WasmPool::new(sns, ins, cfg.queue)));
+        }
+        return Ok(sn);
+    }
+
+    fn _create_one(
+        &self,
+        wasm_path: &str,
+        cfg: &WasmConfig,
+        _module: &Module,
+    ) -> Result<usize, LoadError> {
+        let sn = self.alloc_slot(cfg, _module);
+        let b = self.get_ina(sn).unwrap();
+
+        let r;
+        {
+            let mut _store = b.sto.st.lock().unwrap();
+            let mut ins = WasmInstance::new(wasm_path.to_string(), cfg);
+            ins.sn = sn;
+            r = ins
+                .init(&b.sto, &mut _store, _module)
+                .and_then(|_| ins.run_init(&mut _store))
+                .map(|way| (ins, way));
+        }
+
+        match r {
+            Ok((ins, way)) => {
+                {
+                    let mut w = WS_JSN.write().unwrap();
+                    w[sn] = way;
+                }
                 if let Ok(mut ct) = b.ct.write() {
                     *ct = Some(ins);
                 }
+                return Ok(sn);
+            }
+            Err(e) => {
+                self.drop_slot(sn);
+                return Err(e);
             }
         }
+    }
+
+    pub fn swap_instance(&self, wasm_path: &str, sn: usize, _module: Module) -> Option<usize> {
+        {
+            let mut map = WS_MOD.write().unwrap();
+            map.insert(wasm_path.to_string(), _module);
+        }
+        let mut map = WS_INM.write().unwrap();
+        return map
+            .insert(wasm_path.to_string(), sn as i32)
+            .map(|v| v as usize);
+    }
+
+    pub fn get_ina(&self, sn: usize) -> Option<Arc<WasmInstanceStub>> {
+        let ina = WS_INA.read().unwrap();
+        if let Some(Some(b)) = ina.get(sn) {
+            return Some(b.clone());
+        }
+        return None;
+    }
+
+    pub fn get_path(&self, sn: usize) -> String {
+        if let Some(b) = self.get_ina(sn) {
+            return b.get_path();
+        }
+        return String::new();
+    }
+
+    pub fn get_pool(&self, sn: usize) -> Option<Arc<WasmPool>> {
+        let pol = WS_POL.read().unwrap();
+        return pol.get(&sn).cloned();
+    }
+
+    // what sn stands for right now, kept by a caller across a reload of the module
+    pub fn get_target(&self, sn: usize) -> Option<WasmTarget> {
+        if let Some(pool) = self.get_pool(sn) {
+            return Some(WasmTarget::Pool(pool));
+        }
+        return self.get_ina(sn).map(WasmTarget::One);
+    }
+
+    pub fn get_config(&self, wasm_path: &str) -> Option<WasmConfig> {
+        let map = WS_CFG.read().unwrap();
+        return map.get(wasm_path).cloned();
+    }
+
+    // also forgets the module of a load that failed before it got a slot
+    pub fn drop_instance(&self, wasm_path: &str) -> Option<usize> {
+        {
+            let mut map = WS_MOD.write().unwrap();
+            map.remove(wasm_path);
+        }
+        {
+            let mut map = WS_CFG.write().unwrap();
+            map.remove(wasm_path);
+        }
+        let sn;
         {
             let mut map = WS_INM.write().unwrap();
-            map.insert(wasm_path.to_string(), sn as i32);
+            match map.remove(wasm_path) {
+                Some(v) => sn = v as usize,
+                None => return None,
+            }
         }
 
-        self._wasm_init(sn);
-        return true;
+        self.drop_slot(sn);
+        return Some(sn);
     }
 
-    fn _wasm_init(&self, sn: usize) {
-        if let Some(sto) = WS_STO.get(sn) {
-            if let Some(_ws) = sto {
-                if let Some(ina) = WS_INA.get(sn) {
-                    if let Some(b) = ina {
-                        let mut c = b.ct.write().unwrap();
-                        if let Some(t) = c.as_mut() {
-                            let mut _store = _ws.st.lock().unwrap();
-                            let stc = _store.as_context_mut();
-
-                            let way = t.sminit.as_mut().unwrap().call(stc, LOAD_WAY).unwrap();
-                            {
-                                let mut w = WS_JSN.write().unwrap();
-                                w[sn] = way;
-                            }
-                        }
-                    }
+    pub fn alloc_slot(&self, cfg: &WasmConfig, _module: &Module) -> usize {
+        let sn = WS_UTL.get_ssn();
+        {
+            let mut ina = WS_INA.write().unwrap();
+            if ina.len() <= sn {
+                ina.resize(sn + 1, None);
+            }
+            ina[sn] = Some(Arc::new(WasmInstanceStub::new(sn, cfg, _module)));
+        }
+        {
+            let mut w = WS_JSN.write().unwrap();
+            if w.len() <= sn {
+                w.resize(sn + 1, 0);
+            }
+            w[sn] = 0;
+        }
+        return sn;
+    }
+
+    // drops every pooled instance when sn is the primary of a pool
+    pub fn drop_slot(&self, sn: usize) {
+        let op;
+        {
+            let mut pol = WS_POL.write().unwrap();
+            op = pol.remove(&sn);
+        }
+        match op {
+            Some(pool) => {
+                for psn in pool.sns.iter() {
+                    self._drop_one(*psn);
                 }
             }
+            None => self._drop_one(sn),
         }
     }
+
+    fn _drop_one(&self, sn: usize) {
+        let op;
+        {
+            let mut ina = WS_INA.write().unwrap();
+            op = ina.get_mut(sn).and_then(|x| x.take());
+        }
+
+        // waits for in-flight calls, store lock first like every call
+        if let Some(b) = op {
+            let _store = match b.sto.st.lock() {
+                Ok(_store) => _store,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            match b.ct.write() {
+                Ok(mut ct) => *ct = None,
+                Err(poisoned) => *poisoned.into_inner() = None,
+            }
+        }
+        {
+            let mut w = WS_JSN.write().unwrap();
+            if let Some(x) = w.get_mut(sn) {
+                *x = 0;
+            }
+        }
+
+        WS_UTL.free_ssn(sn);
+    }
+}
+
+pub enum WasmTarget {
+    One(Arc<WasmInstanceStub>),
+    Pool(Arc<WasmPool>),
 }
 
 pub struct WasmInstanceStub {
-    sn: usize,
+    pub sto: WasmStoreStub,
     pub ct: RwLock<Option<WasmInstance>>,
 }
 
 impl WasmInstanceStub {
-    fn new(id: usize) -> Self {
+    fn new(id: usize, cfg: &WasmConfig, _module: &Module) -> Self {
         WasmInstanceStub {
-            sn: id,
+            sto: WasmStoreStub::new(id, cfg, _module),
             ct: RwLock::new(None),
         }
     }
 
-    fn _do_call(&self, mut _caller: StoreContextMut<'_, WasmState>, ptr: i32) -> SmDtonBuffer {
+    fn _do_call(
+        &self,
+        mut _caller: StoreContextMut<'_, WasmState>,
+        name: &str,
+        ptr: i32,
+        opt: &CallOption,
+    ) -> Result<SmDtonBuffer, CallError> {
         let rd = self.ct.read().unwrap();
         if let Some(ref ins) = *rd {
+            let budget = opt.fuel.or(ins.cfg.fuel).unwrap_or(u64::MAX);
+            if WS_UTL.fuel {
+                _caller.set_fuel(budget).unwrap();
+            }
+            let mut deadline = opt.deadline.or(ins.cfg.deadline);
+            let outer = WS_END.get();
+            if let Some(end) = outer {
+                let left = end.saturating_duration_since(Instant::now());
+                deadline = Some(deadline.map_or(left, |d| d.min(left)));
+            }
+            if WS_UTL.epoch {
+                if let Some(d) = deadline {
+                    _caller.set_epoch_deadline(WS_UTL.get_ticks(d));
+                }
+            }
+
+            WS_END.set(deadline.map(|d| Instant::now() + d));
             let stc1 = _caller.as_context_mut();
-            let ptr_ret = ins.smcall.as_ref().unwrap().call(stc1, (ptr, 1)).unwrap() as usize;
+            let r = ins.smcall.as_ref().unwrap().call(stc1, (ptr, 1));
+            WS_END.set(outer);
 
-            if WS_UTL.is_json(ins.sn) {
-                let stc2 = _caller.as_context_mut();
-                let mem = ins.instance.unwrap().get_memory(stc2, "memory").unwrap();
-                let ret = WS_UTL.get_buffer_text(_caller.as_context_mut(), mem, ptr_ret);
+            let mut used = 0;
+            if WS_UTL.fuel {
+                used = budget - _caller.get_fuel().unwrap_or(0);
+                _caller.set_fuel(u64::MAX).unwrap();
+            }
+            if WS_UTL.epoch {
+                _caller.set_epoch_deadline(EPOCH_NEVER);
+            }
+            wasm_stat::add_call(&ins.path, name, used);
+
+            let ptr_ret = match r {
+                Ok(v) => v as usize,
+                Err(e) => {
+                    match e.downcast_ref::<Trap>() {
+                        Some(Trap::OutOfFuel) => {
+                            wasm_stat::add_out_of_fuel(&ins.path);
+                            return Err(CallError::OutOfFuel(budget, get_backtrace(&e)));
+                        }
+                        Some(Trap::Interrupt) => {
+                            wasm_stat::add_timeout(&ins.path);
+                            let ms = deadline.unwrap_or_default().as_millis();
+                            return Err(CallError::Timeout(ms, get_backtrace(&e)));
+                        }
+                        _ => {}
+                    }
+                    return Err(CallError::from_trap(&e));
+                }
+            };
 
-                let stc3 = _caller.as_context_mut();
-                ins.smdealloc
-                    .as_ref()
-                    .unwrap()
-                    .call(stc3, ptr_ret as i32)
-                    .unwrap();
+            let mem = ins.memory.ok_or(CallError::Unavailable)?;
 
-                let jsn = json::parse(&ret).unwrap();
+            let smb;
+            if WS_UTL.is_json(ins.sn) {
+                let ret = WS_UTL.get_buffer_text(_caller.as_context_mut(), mem, ptr_ret);
+                let ret = ret.ok_or(CallError::Decode("output text".to_string()))?;
+                let jsn = json::parse(&ret).map_err(|e| CallError::Decode(e.to_string()))?;
                 let mut sb = SmDtonBuilder::new_from_json(&jsn);
-                return sb.build();
+                smb = sb.build();
             } else {
-                let _name;
-                let smb;
-                if WS_UTL.is_json(ins.sn) {
-                    smb = SmDtonBuffer::new();
-                } else {
-                    let stc2 = _caller.as_context_mut();
-                    let mem = ins.instance.unwrap().get_memory(stc2, "memory").unwrap();
-                    (_name, smb) = WS_UTL.get_buffer_smb(_caller.as_context_mut(), mem, ptr_ret);
-                }
+                let ret = WS_UTL.get_buffer_smb(_caller.as_context_mut(), mem, ptr_ret);
+                (_, smb) = ret.ok_or(CallError::Decode("output buffer".to_string()))?;
+            }
 
-                let stc3 = _caller.as_context_mut();
-                ins.smdealloc
-                    .as_ref()
-                    .unwrap()
-                    .call(stc3, ptr_ret as i32)
-                    .unwrap();
-                return smb;
+            let stc3 = _caller.as_context_mut();
+            ins.smdealloc
+                .as_ref()
+                .unwrap()
+                .call(stc3, ptr_ret as i32)
+                .map_err(|e| CallError::from_trap(&e))?;
+            return Ok(smb);
+        }
+        return Err(CallError::Unavailable);
+    }
+
+    pub fn call(&self, name: &str, smb: &SmDtonBuffer, opt: &CallOption) -> SmDtonBuffer {
+        return match self.call_live(name, smb, opt) {
+            Some(ret) => ret,
+            None => CallError::Unavailable.to_smb("", name),
+        };
+    }
+
+    // None when the slot was dropped before the call got the store
+    pub fn call_live(
+        &self,
+        name: &str,
+        smb: &SmDtonBuffer,
+        opt: &CallOption,
+    ) -> Option<SmDtonBuffer> {
+        let mut _store = match self.sto.st.lock() {
+            Ok(_store) => _store,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if self.is_dropped() {
+            return None;
+        }
+        // the restart after the last fault failed, try it again
+        if !self.is_ready() {
+            self.recover(&mut _store);
+            if !self.is_ready() {
+                let path = self.get_path();
+                return Some(CallError::Unavailable.to_smb(&path, name));
+            }
+        }
+        let r = self
+            .output_memory(_store.as_context_mut(), name, smb)
+            .and_then(|ptr| self._do_call(_store.as_context_mut(), name, ptr, opt));
+
+        match r {
+            Ok(ret) => {
+                return Some(ret);
+            }
+            Err(e) => {
+                let path = self.get_path();
+                smu.log(&format!(
+                    "--- wasm call failed --- {} --- {} --- {} ---",
+                    path, name, e
+                ));
+                // still under the store lock, the next call waits for the new instance
+                if e.is_guest_fault() {
+                    self.recover(&mut _store);
+                }
+                return Some(e.to_smb(&path, name));
             }
         }
-        return SmDtonBuffer::new();
     }
 
-    pub fn call(&self, ptr: i32) -> SmDtonBuffer {
-        if let Some(a) = WS_STO.get(self.sn) {
-            if let Some(_ws) = a {
-                match _ws.st.lock() {
-                    Ok(mut _store) => {
-                        return self._do_call(_store.as_context_mut(), ptr);
-                    }
-                    Err(poisoned) => {
-                        let mut _store = poisoned.into_inner();
-                        return self._do_call(_store.as_context_mut(), ptr);
-                    }
+    fn is_dropped(&self) -> bool {
+        let rd = match self.ct.read() {
+            Ok(rd) => rd,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        return rd.is_none();
+    }
+
+    fn is_ready(&self) -> bool {
+        let rd = match self.ct.read() {
+            Ok(rd) => rd,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        return rd.as_ref().is_some_and(|ins| ins.ready);
+    }
+
+    pub fn get_path(&self) -> String {
+        let rd = match self.ct.read() {
+            Ok(rd) => rd,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Some(ref ins) = *rd {
+            return ins.path.clone();
+        }
+        return String::new();
+    }
+
+    // drops a trapped instance and instantiates the cached module again in the same slot,
+    // on failure the slot keeps an instance that is not ready
+    fn recover(&self, _store: &mut Store<WasmState>) {
+        let mut ct = match self.ct.write() {
+            Ok(ct) => ct,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let old = match ct.take() {
+            Some(ins) => ins,
+            None => return,
+        };
+
+        *_store = self.sto.new_store();
+        self.sto.st.clear_poison();
+
+        let _module;
+        {
+            let map = WS_MOD.read().unwrap();
+            _module = map.get(&old.path).cloned();
+        }
+
+        let mut ins = WasmInstance::new(old.path.clone(), &old.cfg);
+        ins.sn = old.sn;
+        let r = match _module {
+            Some(ref m) => ins
+                .init(&self.sto, _store, m)
+                .and_then(|_| ins.run_init(_store)),
+            None => Err(LoadError::NotLoaded(old.path.clone())),
+        };
+        match r {
+            Ok(way) => {
+                {
+                    let mut w = WS_JSN.write().unwrap();
+                    w[ins.sn] = way;
                 }
+                wasm_stat::add_restart(&ins.path);
+                smu.log(&format!(
+                    "--- wasm restarted --- {} --- {} ---",
+                    ins.path, ins.sn
+                ));
+                *ct = Some(ins);
+            }
+            Err(e) => {
+                wasm_stat::add_restart_failed(&old.path);
+                smu.log(&format!("--- wasm restart failed --- {} ---", e));
+                let mut lost = WasmInstance::new(old.path.clone(), &old.cfg);
+                lost.sn = old.sn;
+                *ct = Some(lost);
             }
         }
-        return SmDtonBuffer::new();
     }
 
     pub fn output_memory(
@@ -182,11 +532,13 @@ impl WasmInstanceStub {
         mut _caller: StoreContextMut<'_, WasmState>,
         name: &str,
         smb: &SmDtonBuffer,
-    ) -> i32 {
+    ) -> Result<i32, CallError> {
         let rd = self.ct.read().unwrap();
         if let Some(ref ins) = *rd {
             if WS_UTL.is_json(ins.sn) {
-                let txt = smb.stringify().unwrap();
+                let txt = smb
+                    .stringify()
+                    .ok_or(CallError::Decode("input text".to_string()))?;
                 let bvo = txt.as_bytes();
 
                 let stc1 = _caller.as_context_mut();
@@ -195,16 +547,15 @@ impl WasmInstanceStub {
                     .as_ref()
                     .unwrap()
                     .call(stc1, bvo.len() as i32)
-                    .unwrap() as usize;
+                    .map_err(|e| CallError::from_trap(&e))? as usize;
 
-                let stc2 = _caller.as_context_mut();
-                let m1 = ins.instance.unwrap().get_memory(stc2, "memory");
-                let mem = m1.unwrap();
+                let mem = ins.memory.ok_or(CallError::Unavailable)?;
 
                 let stc3 = _caller.as_context_mut();
-                mem.write(stc3, poff + SZ::LEN, bvo).unwrap();
+                mem.write(stc3, poff + SZ::LEN, bvo)
+                    .map_err(|e| CallError::Trap(e.to_string(), Vec::new()))?;
 
-                return poff as i32;
+                return Ok(poff as i32);
             } else {
                 let mut nmbytes = name.as_bytes().to_vec();
                 nmbytes.push(0);
@@ -219,61 +570,35 @@ impl WasmInstanceStub {
                     .as_ref()
                     .unwrap()
                     .call(stc1, total as i32)
-                    .unwrap() as usize;
+                    .map_err(|e| CallError::from_trap(&e))? as usize;
 
-                let stc2 = _caller.as_context_mut();
-                let m1 = ins.instance.unwrap().get_memory(stc2, "memory");
-                let mem = m1.unwrap();
+                let mem = ins.memory.ok_or(CallError::Unavailable)?;
 
-                let fb = (2 as u8).to_le_bytes();
-                let stc6 = _caller.as_context_mut();
-                mem.write(stc6, poff + SZ::LEN, &fb).unwrap();
+                let mut piece: Vec<u8> = Vec::with_capacity(total);
+                piece.extend_from_slice(&(2 as u8).to_le_bytes());
+                piece.extend_from_slice(&(nmlen as u16).to_le_bytes());
+                piece.extend_from_slice(&nmbytes);
+                piece.extend_from_slice(buf);
 
-                let u1 = (nmlen as u16).to_le_bytes();
                 let stc3 = _caller.as_context_mut();
-                mem.write(stc3, poff + SZ::LEN_TY, &u1).unwrap();
-
-                let stc4 = _caller.as_context_mut();
-                mem.write(stc4, poff + SZ::LEN_TY_NM, &nmbytes).unwrap();
-
-                let stc5 = _caller.as_context_mut();
-                mem.write(stc5, poff + SZ::LEN_TY_NM + nmlen, buf).unwrap();
+                mem.write(stc3, poff + SZ::LEN, &piece)
+                    .map_err(|e| CallError::Trap(e.to_string(), Vec::new()))?;
 
-                return poff as i32;
+                return Ok(poff as i32);
             }
-        } else {
         }
-        return 0;
-    }
-
-    pub fn set_input(&self, name: &str, smb: &SmDtonBuffer) -> i32 {
-        if let Some(a) = WS_STO.get(self.sn) {
-            if let Some(_ws) = a {
-                let moff;
-                {
-                    match _ws.st.lock() {
-                        Ok(mut _store) => {
-                            moff = self.output_memory(_store.as_context_mut(), name, smb);
-                        }
-                        Err(poisoned) => {
-                            let mut _store = poisoned.into_inner();
-                            moff = self.output_memory(_store.as_context_mut(), name, smb);
-                        }
-                    }
-                }
-                return moff;
-            }
-        }
-        return 0;
+        return Err(CallError::Unavailable);
     }
 }
 
 pub struct WasmInstance {
     path: String,
-    page: i32,
+    cfg: WasmConfig,
     ready: bool,
     pub sn: usize,
     pub instance: Option<Instance>,
+    // exchange memory, WasmConfig::memory
+    pub memory: Option<Memory>,
 
     pub sminit: Option<TypedFunc<i32, i32>>,
     smcall: Option<TypedFunc<(i32, i32), i32>>,
@@ -282,13 +607,14 @@ pub struct WasmInstance {
 }
 
 impl WasmInstance {
-    pub fn new(wasm_path: String, pagenum: i32) -> WasmInstance {
+    pub fn new(wasm_path: String, cfg: &WasmConfig) -> WasmInstance {
         WasmInstance {
             path: wasm_path.to_string(),
-            page: pagenum,
+            cfg: cfg.clone(),
             ready: false,
             sn: 0,
             instance: None,
+            memory: None,
             sminit: None,
             smcall: None,
             smalloc: None,
@@ -296,71 +622,70 @@ impl WasmInstance {
         }
     }
 
-    pub fn init(&mut self) {
-        if !WS_UTL.check_module(&self.path) {
-            return;
+    pub fn init(
+        &mut self,
+        _ws: &WasmStoreStub,
+        _store: &mut Store<WasmState>,
+        _module: &Module,
+    ) -> Result<(), LoadError> {
+        _store.data_mut().limiter.set(&self.path, &self.cfg);
+        _store.data_mut().output.set(&self.path, &self.cfg);
+        _store.data_mut().det.set(&self.path, &self.cfg);
+        _store.data_mut().args.set(&self.cfg);
+        #[cfg(feature = "wasi")]
+        if self.cfg.wasi {
+            _store.data_mut().wasi = Some(wasm_wasi::get_ctx(&self.path, self.sn, &self.cfg)?);
         }
-        let map = WS_MOD.read().unwrap();
-        let _module = map.get(&self.path).unwrap().as_ref().map(|x| x).unwrap();
-
-        self.sn = WS_UTL.get_ssn();
-
-        let _ws = WS_STO[self.sn].as_ref().map(|x| x).unwrap();
-        let _instance = _ws.get_instance(&_module);
-
-        let mut _store = _ws.st.lock().unwrap();
+        let _instance = _ws.get_instance(_store, &self.path, &_module)?;
 
         let stc0 = _store.as_context_mut();
-        let opmem = _instance.get_memory(stc0, "memory");
+        let opmem = _instance.get_memory(stc0, &self.cfg.memory);
         match opmem {
             Some(mem) => {
+                self.memory = Some(mem);
+                _store.data_mut().memory = Some(mem);
+
                 // for memory
                 let stc1 = _store.as_context_mut();
                 let msize = mem.size(stc1) as i32;
 
-                if self.page > msize {
+                if self.cfg.page > msize {
                     let stc2 = _store.as_context_mut();
-                    let r = mem.grow(stc2, (self.page - msize) as u64);
+                    let r = mem.grow(stc2, (self.cfg.page - msize) as u64);
                     match r {
                         Ok(_size) => {
                             let stc3 = _store.as_context_mut();
                             let newsize = mem.size(stc3) as i32;
-                            println!(
+                            smu.log(&format!(
                                 "--- {} --- original --- {} --- new page number --- {} ---",
                                 self.path, _size, newsize
-                            );
+                            ));
                         }
                         _ => {}
                     }
                 } else {
-                    println!(
+                    smu.log(&format!(
                         "--- {} --- original page number --- {} ---",
                         self.path, msize
-                    );
+                    ));
                 }
             }
-            _ => {}
+            _ => {
+                return Err(LoadError::MissingExport(
+                    self.path.clone(),
+                    self.cfg.memory.clone(),
+                ));
+            }
         }
 
-        let stc1 = _store.as_context_mut();
-        let _sminit: TypedFunc<i32, i32> = _instance
-            .get_typed_func::<i32, i32>(stc1, "sminit")
-            .unwrap();
-
-        let stc2 = _store.as_context_mut();
-        let _smcall: TypedFunc<(i32, i32), i32> = _instance
-            .get_typed_func::<(i32, i32), i32>(stc2, "smcall")
-            .unwrap();
-
-        let stc3 = _store.as_context_mut();
-        let _smalloc: TypedFunc<i32, i32> = _instance
-            .get_typed_func::<i32, i32>(stc3, "smalloc")
-            .unwrap();
-
-        let stc4 = _store.as_context_mut();
-        let _smdealloc: TypedFunc<i32, ()> = _instance
-            .get_typed_func::<i32, ()>(stc4, "smdealloc")
-            .unwrap();
+        let _sminit: TypedFunc<i32, i32> =
+            self.get_func::<i32, i32>(_store, &_instance, "sminit")?;
+        let _smcall: TypedFunc<(i32, i32), i32> =
+            self.get_func::<(i32, i32), i32>(_store, &_instance, "smcall")?;
+        let _smalloc: TypedFunc<i32, i32> =
+            self.get_func::<i32, i32>(_store, &_instance, "smalloc")?;
+        let _smdealloc: TypedFunc<i32, ()> =
+            self.get_func::<i32, ()>(_store, &_instance, "smdealloc")?;
 
         self.instance = Some(_instance);
         self.sminit = Some(_sminit);
@@ -369,5 +694,54 @@ impl WasmInstance {
         self.smdealloc = Some(_smdealloc);
 
         self.ready = true;
+        return Ok(());
+    }
+
+    pub fn run_init(&mut self, _store: &mut Store<WasmState>) -> Result<i32, LoadError> {
+        let stc = _store.as_context_mut();
+        match self.sminit.as_ref().unwrap().call(stc, LOAD_WAY) {
+            Ok(way) => {
+                return Ok(way);
+            }
+            Err(e) => {
+                return Err(LoadError::InitTrap(self.path.clone(), format!("{:#}", e)));
+            }
+        }
+    }
+
+    fn get_func<P, R>(
+        &self,
+        _store: &mut Store<WasmState>,
+        _instance: &Instance,
+        name: &str,
+    ) -> Result<TypedFunc<P, R>, LoadError>
+    where
+        P: WasmParams,
+        R: WasmResults,
+    {
+        let stc1 = _store.as_context_mut();
+        let func = match _instance.get_func(stc1, name) {
+            Some(f) => f,
+            None => {
+                return Err(LoadError::MissingExport(
+                    self.path.clone(),
+                    name.to_string(),
+                ));
+            }
+        };
+
+        let stc2 = _store.as_context();
+        match func.typed::<P, R>(stc2) {
+            Ok(f) => {
+                return Ok(f);
+            }
+            Err(e) => {
+                return Err(LoadError::WrongSignature(
+                    self.path.clone(),
+                    name.to_string(),
+                    format!("{:#}", e),
+                ));
+            }
+        }
     }
 }
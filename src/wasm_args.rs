@@ -0,0 +1,104 @@
+use wasmtime::*;
+
+use crate::wasm_config::WasmConfig;
+use crate::wasm_import::{ERRNO_FAULT, WasmState};
+
+// argv and environ of one instance, each entry nul terminated
+pub struct WasmArgs {
+    args: Vec<Vec<u8>>,
+    envs: Vec<Vec<u8>>,
+}
+
+impl WasmArgs {
+    pub fn new() -> WasmArgs {
+        WasmArgs {
+            args: Vec::new(),
+            envs: Vec::new(),
+        }
+    }
+
+    pub fn set(&mut self, cfg: &WasmConfig) {
+        self.args = cfg.args.iter().map(|a| get_cstr(a)).collect();
+        self.envs = cfg
+            .env
+            .iter()
+            .map(|(k, v)| get_cstr(&format!("{}={}", k, v)))
+            .collect();
+    }
+
+    fn get_list(&self, env: bool) -> &Vec<Vec<u8>> {
+        if env {
+            return &self.envs;
+        }
+        return &self.args;
+    }
+}
+
+fn get_cstr(s: &str) -> Vec<u8> {
+    let mut v = s.as_bytes().to_vec();
+    v.push(0);
+    return v;
+}
+
+// count at count_ptr, total bytes of the strings at size_ptr
+pub fn sizes_get(
+    mut _caller: Caller<'_, WasmState>,
+    env: bool,
+    count_ptr: i32,
+    size_ptr: i32,
+) -> i32 {
+    let mem = match _caller.data().memory {
+        Some(m) => m,
+        None => return ERRNO_FAULT,
+    };
+    let list = _caller.data().args.get_list(env);
+    let count = list.len() as u32;
+    let size = list.iter().map(|x| x.len()).sum::<usize>() as u32;
+
+    if mem
+        .write(
+            &mut _caller,
+            count_ptr as u32 as usize,
+            &count.to_le_bytes(),
+        )
+        .is_err()
+    {
+        return ERRNO_FAULT;
+    }
+    if mem
+        .write(&mut _caller, size_ptr as u32 as usize, &size.to_le_bytes())
+        .is_err()
+    {
+        return ERRNO_FAULT;
+    }
+    return 0;
+}
+
+// u32 pointers at ptrs, the strings packed from buf
+pub fn get(mut _caller: Caller<'_, WasmState>, env: bool, ptrs: i32, buf: i32) -> i32 {
+    let mem = match _caller.data().memory {
+        Some(m) => m,
+        None => return ERRNO_FAULT,
+    };
+    let list = _caller.data().args.get_list(env);
+
+    let mut table: Vec<u8> = Vec::with_capacity(list.len() * 4);
+    let mut data: Vec<u8> = Vec::new();
+    let base = buf as u32;
+    for x in list {
+        let p = base.wrapping_add(data.len() as u32);
+        table.extend_from_slice(&p.to_le_bytes());
+        data.extend_from_slice(x);
+    }
+
+    if mem
+        .write(&mut _caller, ptrs as u32 as usize, &table)
+        .is_err()
+    {
+        return ERRNO_FAULT;
+    }
+    if mem.write(&mut _caller, base as usize, &data).is_err() {
+        return ERRNO_FAULT;
+    }
+    return 0;
+}
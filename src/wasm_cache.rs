@@ -0,0 +1,131 @@
+use sha2::{Digest, Sha256};
+use smcore::smu;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use wasmtime::*;
+
+use crate::wasm_error::LoadError;
+
+const EXT: &str = "cwasm";
+
+// temp file names of this process, two threads may store the same artifact
+static WS_TMP: AtomicU64 = AtomicU64::new(0);
+
+// feeds a Hash impl into sha256, finish is never used
+struct ShaHasher(Sha256);
+
+impl Hasher for ShaHasher {
+    fn finish(&self) -> u64 {
+        return 0;
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+}
+
+// sha256 of wasm bytes + engine settings + wasmtime version, in hex
+fn get_key(engine: &Engine, bytes: &[u8]) -> String {
+    let mut h = ShaHasher(Sha256::new());
+    h.0.update(bytes);
+    engine.precompile_compatibility_hash().hash(&mut h);
+    let digest = h.0.finalize();
+    return digest.iter().map(|b| format!("{:02x}", b)).collect();
+}
+
+// one artifact name prefix per wasm path, so stale ones can be found
+fn get_prefix(wasm_path: &str) -> String {
+    let p = Path::new(wasm_path);
+    let stem = p
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let full = fs::canonicalize(p).unwrap_or(p.to_path_buf());
+    let digest = Sha256::digest(full.as_os_str().as_encoded_bytes());
+    let hex: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    return format!("{}.{}.", stem, hex);
+}
+
+pub fn load(engine: &Engine, dir: &Path, wasm_path: &str) -> Result<Module, LoadError> {
+    let bytes = match fs::read(wasm_path) {
+        Ok(b) => b,
+        Err(_) => {
+            return Err(LoadError::NotFound(wasm_path.to_string()));
+        }
+    };
+
+    let prefix = get_prefix(wasm_path);
+    let name = format!("{}{}.{}", prefix, get_key(engine, &bytes), EXT);
+    let file = dir.join(&name);
+
+    if file.is_file() {
+        // the key covers the engine, the file header is checked again by wasmtime
+        match unsafe { Module::deserialize_file(engine, &file) } {
+            Ok(_mod) => {
+                return Ok(_mod);
+            }
+            Err(e) => {
+                smu.log(&format!(
+                    "--- cache drop --- {} --- {:#} ---",
+                    file.display(),
+                    e
+                ));
+                let _ = fs::remove_file(&file);
+            }
+        }
+    }
+
+    // text too, like Module::from_file without a cache
+    let _mod = match Module::new(engine, &bytes) {
+        Ok(m) => m,
+        Err(e) => {
+            return Err(LoadError::Compile(
+                wasm_path.to_string(),
+                format!("{:#}", e),
+            ));
+        }
+    };
+
+    if let Err(e) = store(&_mod, dir, &file) {
+        smu.log(&format!(
+            "--- cache write failed --- {} --- {:#} ---",
+            file.display(),
+            e
+        ));
+        return Ok(_mod);
+    }
+    remove_stale(dir, &prefix, &name);
+    return Ok(_mod);
+}
+
+// write to a temp file then rename, readers never see a partial artifact
+fn store(_mod: &Module, dir: &Path, file: &PathBuf) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    let data = _mod.serialize()?;
+    let seq = WS_TMP.fetch_add(1, Ordering::SeqCst);
+    let tmp = file.with_extension(format!("{}.{}.{}", EXT, std::process::id(), seq));
+    fs::write(&tmp, &data)?;
+    if let Err(e) = fs::rename(&tmp, file) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e.into());
+    }
+    return Ok(());
+}
+
+// artifacts of older builds of the same wasm
+fn remove_stale(dir: &Path, prefix: &str, keep: &str) {
+    let rd = match fs::read_dir(dir) {
+        Ok(rd) => rd,
+        Err(_) => {
+            return;
+        }
+    };
+    for ent in rd.flatten() {
+        let name = ent.file_name().to_string_lossy().to_string();
+        if name != keep && name.starts_with(prefix) && name.ends_with(EXT) {
+            let _ = fs::remove_file(ent.path());
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// virtual clock start and random seed, the same calls give the same bytes
+#[derive(Clone, Debug, Default)]
+pub struct Deterministic {
+    pub start_ms: u64,
+    pub seed: u64,
+}
+
+// host directory the module sees at the guest path
+#[derive(Clone, Debug)]
+pub struct Preopen {
+    pub host: PathBuf,
+    pub guest: String,
+    pub read_only: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct WasmConfig {
+    // memory pages grown up front
+    pub page: i32,
+    // export name of the memory smcall data is exchanged through
+    pub memory: String,
+    // instances of the module, calls go to a free one
+    pub pool: usize,
+    // callers allowed to wait when every pooled instance is busy
+    pub queue: usize,
+    // fuel for each smcall, needs EngineConfig::fuel
+    pub fuel: Option<u64>,
+    // wall-clock limit for each smcall, needs EngineConfig::epoch,
+    // calls it makes through hostcallsm get what is left of it
+    pub deadline: Option<Duration>,
+    // memory can not grow past this many pages
+    pub max_page: Option<usize>,
+    // table elements
+    pub max_table: Option<usize>,
+    // instances in the store of each pooled instance
+    pub max_instance: Option<usize>,
+    // link wasmtime-wasi preview1 in place of the stubs, needs the wasi feature
+    pub wasi: bool,
+    // directories opened for wasi, nothing else of the host is visible
+    pub preopens: Vec<Preopen>,
+    // guest stdout and stderr lines go here instead of smu.log
+    pub log_file: Option<PathBuf>,
+    // virtual clock and seeded random_get, see set_clock and advance_clock
+    // the built-in imports only, a load with wasi set is refused
+    pub deterministic: Option<Deterministic>,
+    // argv of the guest, args[0] is usually the program name
+    pub args: Vec<String>,
+    // environ of the guest, sorted so every run sees the same order
+    pub env: BTreeMap<String, String>,
+}
+
+impl WasmConfig {
+    pub fn new(pagenum: i32) -> WasmConfig {
+        WasmConfig {
+            page: pagenum,
+            memory: "memory".to_string(),
+            pool: 1,
+            queue: 64,
+            fuel: None,
+            deadline: None,
+            max_page: None,
+            max_table: None,
+            max_instance: None,
+            wasi: false,
+            preopens: Vec::new(),
+            log_file: None,
+            deterministic: None,
+            args: Vec::new(),
+            env: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for WasmConfig {
+    fn default() -> Self {
+        WasmConfig::new(0)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EngineConfig {
+    // count instructions so calls can run on a fuel budget
+    pub fuel: bool,
+    // interrupt calls past their deadline, a ticker thread bumps the epoch
+    pub epoch: bool,
+    // ticker period, the granularity of deadlines
+    pub tick: Duration,
+    // keep compiled modules here and reuse them on later starts
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            fuel: false,
+            epoch: false,
+            tick: Duration::from_millis(10),
+            cache_dir: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CallOption {
+    // overrides WasmConfig::fuel for this call
+    pub fuel: Option<u64>,
+    // overrides WasmConfig::deadline for this call
+    pub deadline: Option<Duration>,
+}
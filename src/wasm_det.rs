@@ -0,0 +1,119 @@
+use smcore::smu;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use lazy_static::lazy_static;
+
+use crate::wasm_config::WasmConfig;
+
+lazy_static! {
+    // virtual clock in ms of each deterministic module, shared by its pool
+    pub static ref WS_CLK: RwLock<HashMap<String, Arc<AtomicU64>>> = RwLock::new(HashMap::new());
+}
+
+pub fn get_clock(wasm_path: &str, start_ms: u64) -> Arc<AtomicU64> {
+    let mut map = WS_CLK.write().unwrap();
+    let clk = map
+        .entry(wasm_path.to_string())
+        .or_insert_with(|| Arc::new(AtomicU64::new(start_ms)));
+    return clk.clone();
+}
+
+pub fn drop_clock(wasm_path: &str) {
+    let mut map = WS_CLK.write().unwrap();
+    map.remove(wasm_path);
+}
+
+pub fn set_clock(wasm_path: &str, ms: u64) -> bool {
+    let map = WS_CLK.read().unwrap();
+    if let Some(clk) = map.get(wasm_path) {
+        clk.store(ms, Ordering::SeqCst);
+        return true;
+    }
+    return false;
+}
+
+pub fn advance_clock(wasm_path: &str, ms: u64) -> bool {
+    let map = WS_CLK.read().unwrap();
+    if let Some(clk) = map.get(wasm_path) {
+        clk.fetch_add(ms, Ordering::SeqCst);
+        return true;
+    }
+    return false;
+}
+
+pub fn read_clock(wasm_path: &str) -> Option<u64> {
+    let map = WS_CLK.read().unwrap();
+    return map.get(wasm_path).map(|clk| clk.load(Ordering::SeqCst));
+}
+
+// splitmix64, reproducible from the seed, not for secrets
+pub struct WasmRng {
+    state: u64,
+}
+
+impl WasmRng {
+    pub fn new(seed: u64) -> WasmRng {
+        WasmRng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        return z ^ (z >> 31);
+    }
+
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let v = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&v[..chunk.len()]);
+        }
+    }
+}
+
+// clock and random source of one instance, the host ones unless deterministic
+pub struct WasmDet {
+    clock: Option<Arc<AtomicU64>>,
+    rng: Option<WasmRng>,
+}
+
+impl WasmDet {
+    pub fn new() -> WasmDet {
+        WasmDet {
+            clock: None,
+            rng: None,
+        }
+    }
+
+    pub fn set(&mut self, wasm_path: &str, cfg: &WasmConfig) {
+        match cfg.deterministic {
+            Some(ref d) => {
+                self.clock = Some(get_clock(wasm_path, d.start_ms));
+                self.rng = Some(WasmRng::new(d.seed));
+            }
+            None => {
+                self.clock = None;
+                self.rng = None;
+            }
+        }
+    }
+
+    pub fn get_ms(&self) -> u64 {
+        if let Some(ref clk) = self.clock {
+            return clk.load(Ordering::SeqCst);
+        }
+        return smu.get_current_ms() as u64;
+    }
+
+    // false when the os has no randomness to give
+    pub fn fill(&mut self, buf: &mut [u8]) -> bool {
+        if let Some(ref mut rng) = self.rng {
+            rng.fill(buf);
+            return true;
+        }
+        return getrandom::fill(buf).is_ok();
+    }
+}
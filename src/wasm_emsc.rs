@@ -0,0 +1,387 @@
+use wasmtime::*;
+
+use crate::wasm_error::GuestThrow;
+use crate::wasm_import::WasmState;
+
+// emscripten errno
+const ENODEV: i32 = 19;
+
+// env imports only emscripten emits, besides the emscripten_ and __syscall_ ones
+const NAMES: [&str; 6] = [
+    "_tzset_js",
+    "_localtime_js",
+    "_mmap_js",
+    "_munmap_js",
+    "__cxa_throw",
+    "strftime_l",
+];
+
+const PAGE_SIZE: u64 = 65536;
+// wasm32 heap limit emscripten assumes
+const HEAP_MAX: u64 = 1 << 31;
+// most emscripten grows past the request, 96 MiB
+const OVERGROW_MAX: u64 = 100663296;
+
+// local time of the host, tm fields as emscripten lays them out
+struct LocalTime {
+    fields: [i32; 9],
+    gmtoff: i32,
+    zone: String,
+}
+
+#[cfg(unix)]
+fn get_localtime(t: i64) -> Option<LocalTime> {
+    let tt = t as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let r = unsafe { libc::localtime_r(&tt, &mut tm) };
+    if r.is_null() {
+        return None;
+    }
+    let zone = if tm.tm_zone.is_null() {
+        String::new()
+    } else {
+        unsafe { std::ffi::CStr::from_ptr(tm.tm_zone) }
+            .to_string_lossy()
+            .to_string()
+    };
+    return Some(LocalTime {
+        fields: [
+            tm.tm_sec,
+            tm.tm_min,
+            tm.tm_hour,
+            tm.tm_mday,
+            tm.tm_mon,
+            tm.tm_year,
+            tm.tm_wday,
+            tm.tm_yday,
+            tm.tm_isdst,
+        ],
+        gmtoff: tm.tm_gmtoff as i32,
+        zone: zone,
+    });
+}
+
+// no host timezone database, utc
+#[cfg(not(unix))]
+fn get_localtime(t: i64) -> Option<LocalTime> {
+    let days = t.div_euclid(86400);
+    let secs = t.rem_euclid(86400) as i32;
+
+    // civil from days, proleptic gregorian
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let mday = doy - (153 * mp + 2) / 5 + 1;
+    let mon = if mp < 10 { mp + 2 } else { mp - 10 };
+    let year = yoe + era * 400 + if mon < 2 { 1 } else { 0 };
+
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    const CUM: [i64; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+    let yday = CUM[mon as usize] + mday - 1 + if leap && mon > 1 { 1 } else { 0 };
+
+    return Some(LocalTime {
+        fields: [
+            secs % 60,
+            secs / 60 % 60,
+            secs / 3600,
+            mday as i32,
+            mon as i32,
+            (year - 1900) as i32,
+            (days + 4).rem_euclid(7) as i32,
+            yday as i32,
+            0,
+        ],
+        gmtoff: 0,
+        zone: "UTC".to_string(),
+    });
+}
+
+pub fn is_emscripten(module: &Module) -> bool {
+    for imp in module.imports() {
+        if imp.module() != "env" {
+            continue;
+        }
+        let name = imp.name();
+        if name.starts_with("emscripten_") || name.starts_with("__syscall_") {
+            return true;
+        }
+        if NAMES.contains(&name) {
+            return true;
+        }
+    }
+    return false;
+}
+
+fn get_memory(_caller: &Caller<'_, WasmState>) -> Option<Memory> {
+    return _caller.data().memory;
+}
+
+// time split in two i32, tm written at tm_ptr
+pub fn localtime_js(mut _caller: Caller<'_, WasmState>, lo: i32, hi: i32, tm_ptr: i32) {
+    let t = ((hi as i64) << 32) | (lo as u32 as i64);
+    let lt = match get_localtime(t) {
+        Some(lt) => lt,
+        None => return,
+    };
+    let mem = match get_memory(&_caller) {
+        Some(m) => m,
+        None => return,
+    };
+
+    let mut piece: Vec<u8> = Vec::with_capacity(40);
+    for v in lt.fields {
+        piece.extend_from_slice(&v.to_le_bytes());
+    }
+    piece.extend_from_slice(&lt.gmtoff.to_le_bytes());
+    let _ = mem.write(&mut _caller, tm_ptr as u32 as usize, &piece);
+}
+
+// seconds west of utc at timezone, whether dst exists at daylight,
+// two char* at tzname, set only when the module exports malloc
+pub fn tzset_js(mut _caller: Caller<'_, WasmState>, timezone: i32, daylight: i32, tzname: i32) {
+    let mem = match get_memory(&_caller) {
+        Some(m) => m,
+        None => return,
+    };
+
+    // january and july of the current year, as emscripten does in js
+    let now = _caller.data().det.get_ms() as i64 / 1000;
+    let year_start = match get_localtime(now) {
+        Some(lt) => now - lt.fields[7] as i64 * 86400,
+        None => now,
+    };
+    let winter = get_localtime(year_start);
+    let summer = get_localtime(year_start + 181 * 86400);
+    let (winter, summer) = match (winter, summer) {
+        (Some(w), Some(s)) => (w, s),
+        _ => return,
+    };
+
+    // the standard offset is the one further west
+    let std_off = winter.gmtoff.min(summer.gmtoff);
+    let has_dst = (winter.gmtoff != summer.gmtoff) as i32;
+    let _ = mem.write(
+        &mut _caller,
+        timezone as u32 as usize,
+        &(-std_off).to_le_bytes(),
+    );
+    let _ = mem.write(
+        &mut _caller,
+        daylight as u32 as usize,
+        &has_dst.to_le_bytes(),
+    );
+
+    let (std_name, dst_name) = if summer.gmtoff < winter.gmtoff {
+        (summer.zone, winter.zone)
+    } else {
+        (winter.zone, summer.zone)
+    };
+    let p1 = put_cstr(&mut _caller, mem, &std_name);
+    let p2 = put_cstr(&mut _caller, mem, &dst_name);
+    if let (Some(p1), Some(p2)) = (p1, p2) {
+        let mut piece: Vec<u8> = Vec::with_capacity(8);
+        piece.extend_from_slice(&p1.to_le_bytes());
+        piece.extend_from_slice(&p2.to_le_bytes());
+        let _ = mem.write(&mut _caller, tzname as u32 as usize, &piece);
+    }
+}
+
+// copies s into a block from the guest malloc
+fn put_cstr(_caller: &mut Caller<'_, WasmState>, mem: Memory, s: &str) -> Option<i32> {
+    let malloc = _caller.get_export("malloc")?.into_func()?;
+    let malloc = malloc.typed::<i32, i32>(&*_caller).ok()?;
+    let mut bytes = s.as_bytes().to_vec();
+    bytes.push(0);
+    let ptr = malloc.call(&mut *_caller, bytes.len() as i32).ok()?;
+    if ptr == 0 {
+        return None;
+    }
+    mem.write(&mut *_caller, ptr as u32 as usize, &bytes).ok()?;
+    return Some(ptr);
+}
+
+// grows with the same overgrowth steps as emscripten, 1 on success
+pub fn resize_heap(mut _caller: Caller<'_, WasmState>, requested: i32) -> i32 {
+    let mem = match get_memory(&_caller) {
+        Some(m) => m,
+        None => return 0,
+    };
+    let requested = requested as u32 as u64;
+    let old = mem.data_size(&_caller) as u64;
+    if requested <= old {
+        return 1;
+    }
+    if requested > HEAP_MAX {
+        return 0;
+    }
+
+    // cutDown 1, 2, 4 of emscripten_resize_heap in library.js
+    for cut in [1u64, 2, 4] {
+        let over = (old + old / (5 * cut)).min(requested + OVERGROW_MAX);
+        let size = requested.max(over).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+        let size = size.min(HEAP_MAX);
+        let pages = (size - old).div_ceil(PAGE_SIZE);
+        if mem.grow(&mut _caller, pages).is_ok() {
+            return 1;
+        }
+    }
+    return 0;
+}
+
+// there is no file system behind the fds, anonymous maps never get here
+pub fn mmap_js(_caller: Caller<'_, WasmState>) -> i32 {
+    return -ENODEV;
+}
+
+// nothing was mapped, nothing to write back
+pub fn munmap_js(_caller: Caller<'_, WasmState>) -> i32 {
+    return 0;
+}
+
+pub fn memcpy_js(mut _caller: Caller<'_, WasmState>, dest: i32, src: i32, num: i32) {
+    let mem = match get_memory(&_caller) {
+        Some(m) => m,
+        None => return,
+    };
+    let (dest, src, num) = (
+        dest as u32 as usize,
+        src as u32 as usize,
+        num as u32 as usize,
+    );
+    let data = mem.data_mut(&mut _caller);
+    if src.checked_add(num).is_some_and(|e| e <= data.len())
+        && dest.checked_add(num).is_some_and(|e| e <= data.len())
+    {
+        data.copy_within(src..src + num, dest);
+    }
+}
+
+// longest name read from guest memory
+const NAME_MAX: usize = 1024;
+
+fn get_cstr(_caller: &Caller<'_, WasmState>, mem: Memory, ptr: u32) -> Option<String> {
+    let data = mem.data(_caller);
+    let start = ptr as usize;
+    if start == 0 || start >= data.len() {
+        return None;
+    }
+    let end = (start + NAME_MAX).min(data.len());
+    let len = data[start..end].iter().position(|b| *b == 0)?;
+    return Some(String::from_utf8_lossy(&data[start..start + len]).to_string());
+}
+
+fn get_u32(_caller: &Caller<'_, WasmState>, mem: Memory, ptr: u32) -> Option<u32> {
+    let mut u8a4: [u8; 4] = [0; 4];
+    mem.read(_caller, ptr as usize, &mut u8a4).ok()?;
+    return Some(u32::from_le_bytes(u8a4));
+}
+
+// __cxa_demangle(name, 0, 0, 0) returns a malloc'd string
+fn demangle(_caller: &mut Caller<'_, WasmState>, mem: Memory, name_ptr: u32) -> Option<String> {
+    let f = _caller.get_export("__cxa_demangle")?.into_func()?;
+    let f = f.typed::<(i32, i32, i32, i32), i32>(&*_caller).ok()?;
+    let out = f.call(&mut *_caller, (name_ptr as i32, 0, 0, 0)).ok()?;
+    let name = get_cstr(_caller, mem, out as u32);
+    if let Some(free) = _caller.get_export("free").and_then(|e| e.into_func()) {
+        if let Ok(free) = free.typed::<i32, ()>(&*_caller) {
+            let _ = free.call(&mut *_caller, out);
+        }
+    }
+    return name;
+}
+
+// mangled names of the itanium abi type_info classes
+const SI_CLASS: &str = "N10__cxxabiv120__si_class_type_infoE";
+const VMI_CLASS: &str = "N10__cxxabiv121__vmi_class_type_infoE";
+const STD_EXCEPTION: &str = "St9exception";
+// base classes followed, a type_info cycle in a corrupt heap stops here
+const BASE_DEPTH: u32 = 32;
+const BASE_MAX: u32 = 64;
+
+// offset of the std::exception subobject when tinfo derives from it,
+// through the __si_class_type_info and __vmi_class_type_info base lists
+fn get_exception_offset(
+    _caller: &Caller<'_, WasmState>,
+    mem: Memory,
+    tinfo: u32,
+    depth: u32,
+) -> Option<u32> {
+    if depth > BASE_DEPTH {
+        return None;
+    }
+    // type_info is [vptr][name], its vtable has the type_info of its own class at -4
+    let name = get_cstr(_caller, mem, get_u32(_caller, mem, tinfo.checked_add(4)?)?)?;
+    if name == STD_EXCEPTION {
+        return Some(0);
+    }
+    let vptr = get_u32(_caller, mem, tinfo)?;
+    let meta = get_u32(_caller, mem, vptr.checked_sub(4)?)?;
+    let kind = get_cstr(_caller, mem, get_u32(_caller, mem, meta.checked_add(4)?)?)?;
+
+    if kind == SI_CLASS {
+        // [vptr][name][base type_info*], the base sits at offset 0
+        let base = get_u32(_caller, mem, tinfo.checked_add(8)?)?;
+        return get_exception_offset(_caller, mem, base, depth + 1);
+    }
+    if kind == VMI_CLASS {
+        // [vptr][name][flags][count][{base type_info*, offset << 8 | flags}...]
+        let count = get_u32(_caller, mem, tinfo.checked_add(12)?)?.min(BASE_MAX);
+        for i in 0..count {
+            let at = tinfo.checked_add(16 + i * 8)?;
+            let base = get_u32(_caller, mem, at)?;
+            let flags = get_u32(_caller, mem, at.checked_add(4)?)?;
+            // a virtual base has no fixed offset, a private one is not catchable
+            if flags & 1 != 0 || flags & 2 == 0 {
+                continue;
+            }
+            if let Some(off) = get_exception_offset(_caller, mem, base, depth + 1) {
+                return off.checked_add(flags >> 8);
+            }
+        }
+    }
+    return None;
+}
+
+// slot 2 of the std::exception vtable, after the two destructors,
+// only called once the type_info shows std::exception is a base
+fn get_what(
+    _caller: &mut Caller<'_, WasmState>,
+    mem: Memory,
+    obj: u32,
+    tinfo: u32,
+) -> Option<String> {
+    let off = get_exception_offset(_caller, mem, tinfo, 0)?;
+    let this = obj.checked_add(off)?;
+    let vptr = get_u32(_caller, mem, this)?;
+    let idx = get_u32(_caller, mem, vptr.checked_add(8)?)?;
+    let table = _caller
+        .get_export("__indirect_function_table")?
+        .into_table()?;
+    let r = table.get(&mut *_caller, idx as u64)?;
+    let f = r.as_func()??.typed::<i32, i32>(&*_caller).ok()?;
+    let p = f.call(&mut *_caller, this as i32).ok()?;
+    return get_cstr(_caller, mem, p as u32);
+}
+
+// (thrown object, std::type_info*, destructor), never returns to the guest
+pub fn cxa_throw(mut _caller: Caller<'_, WasmState>, obj: i32, tinfo: i32) -> Result<()> {
+    let mut th = GuestThrow {
+        tinfo: tinfo,
+        type_name: format!("<type_info 0x{:x}>", tinfo as u32),
+        what: None,
+    };
+
+    if let Some(mem) = get_memory(&_caller) {
+        // std::type_info is (vptr, const char* name)
+        if let Some(name_ptr) = get_u32(&_caller, mem, (tinfo as u32).wrapping_add(4)) {
+            if let Some(mangled) = get_cstr(&_caller, mem, name_ptr) {
+                th.type_name = demangle(&mut _caller, mem, name_ptr).unwrap_or(mangled);
+            }
+        }
+        th.what = get_what(&mut _caller, mem, obj as u32, tinfo as u32);
+    }
+    return Err(Error::new(th));
+}
@@ -0,0 +1,268 @@
+use json::JsonValue;
+use smdton::{SmDtonBuffer, SmDtonBuilder};
+use std::fmt;
+use wasmtime::WasmBacktrace;
+#[cfg(feature = "wasi")]
+use wasmtime_wasi::I32Exit;
+
+pub const ERROR: &str = "$error";
+
+#[derive(Debug)]
+pub enum LoadError {
+    // wasm file does not exist
+    NotFound(String),
+    // path, compiler message
+    Compile(String, String),
+    // path, "module::name" of the import
+    UnresolvedImport(String, String),
+    // path, linker message
+    Instantiate(String, String),
+    // path, export name
+    MissingExport(String, String),
+    // path, export name, type check message
+    WrongSignature(String, String, String),
+    // path, trap message
+    InitTrap(String, String),
+    // path
+    CatalogDecode(String),
+    // path given to reload is not loaded
+    NotLoaded(String),
+    // path was loaded from bytes or wat, reload_wasm has no file to read
+    InMemory(String),
+    // path, why the .cwasm does not fit this engine
+    EngineMismatch(String, String),
+    // path, wasi context message
+    Wasi(String, String),
+}
+
+impl LoadError {
+    pub fn path(&self) -> &str {
+        match self {
+            LoadError::NotFound(p) => p,
+            LoadError::Compile(p, _) => p,
+            LoadError::UnresolvedImport(p, _) => p,
+            LoadError::Instantiate(p, _) => p,
+            LoadError::MissingExport(p, _) => p,
+            LoadError::WrongSignature(p, _, _) => p,
+            LoadError::InitTrap(p, _) => p,
+            LoadError::CatalogDecode(p) => p,
+            LoadError::NotLoaded(p) => p,
+            LoadError::InMemory(p) => p,
+            LoadError::EngineMismatch(p, _) => p,
+            LoadError::Wasi(p, _) => p,
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::NotFound(p) => write!(f, "{} --- wasm file not found", p),
+            LoadError::Compile(p, m) => write!(f, "{} --- compile error --- {}", p, m),
+            LoadError::UnresolvedImport(p, n) => write!(f, "{} --- unresolved import --- {}", p, n),
+            LoadError::Instantiate(p, m) => write!(f, "{} --- instantiate error --- {}", p, m),
+            LoadError::MissingExport(p, n) => write!(f, "{} --- missing export --- {}", p, n),
+            LoadError::WrongSignature(p, n, m) => {
+                write!(f, "{} --- wrong signature --- {} --- {}", p, n, m)
+            }
+            LoadError::InitTrap(p, m) => write!(f, "{} --- sminit trap --- {}", p, m),
+            LoadError::CatalogDecode(p) => write!(f, "{} --- cannot decode smker.get.all", p),
+            LoadError::NotLoaded(p) => write!(f, "{} --- wasm is not loaded", p),
+            LoadError::InMemory(p) => write!(f, "{} --- loaded from memory, no file to reload", p),
+            LoadError::EngineMismatch(p, m) => {
+                write!(f, "{} --- precompiled for another engine --- {}", p, m)
+            }
+            LoadError::Wasi(p, m) => write!(f, "{} --- wasi error --- {}", p, m),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+// raised by proc_exit and abort, the guest must not run on past them
+#[derive(Debug)]
+pub enum GuestExit {
+    Exit(i32),
+    Abort,
+}
+
+impl fmt::Display for GuestExit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GuestExit::Exit(code) => write!(f, "proc_exit --- {}", code),
+            GuestExit::Abort => write!(f, "abort"),
+        }
+    }
+}
+
+impl std::error::Error for GuestExit {}
+
+// raised by __cxa_throw, a c++ exception left the guest
+#[derive(Clone, Debug)]
+pub struct GuestThrow {
+    // std::type_info of the thrown object
+    pub tinfo: i32,
+    // demangled when the module exports __cxa_demangle
+    pub type_name: String,
+    // std::exception::what() when the object has one
+    pub what: Option<String>,
+}
+
+impl fmt::Display for GuestThrow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.what {
+            Some(ref w) => write!(f, "c++ exception --- {} --- {}", self.type_name, w),
+            None => write!(f, "c++ exception --- {}", self.type_name),
+        }
+    }
+}
+
+impl std::error::Error for GuestThrow {}
+
+#[derive(Debug)]
+pub enum CallError {
+    // every pooled instance busy and the wait queue full
+    Busy,
+    // fuel budget used up, budget, backtrace
+    OutOfFuel(u64, Vec<String>),
+    // deadline passed, deadline in ms, backtrace
+    Timeout(u128, Vec<String>),
+    // trap message, backtrace
+    Trap(String, Vec<String>),
+    // input or output not readable
+    Decode(String),
+    // instance dropped or failed to restart
+    Unavailable,
+    // no module registered the $usage
+    UnknownUsage(String),
+    // guest called proc_exit, exit code, backtrace
+    Exit(i32, Vec<String>),
+    // guest called abort, backtrace
+    Abort(Vec<String>),
+    // uncaught c++ exception, backtrace
+    Exception(GuestThrow, Vec<String>),
+}
+
+impl CallError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            CallError::Busy => "busy",
+            CallError::OutOfFuel(_, _) => "out_of_fuel",
+            CallError::Timeout(_, _) => "timeout",
+            CallError::Trap(_, _) => "trap",
+            CallError::Decode(_) => "decode",
+            CallError::Unavailable => "unavailable",
+            CallError::UnknownUsage(_) => "unknown_usage",
+            CallError::Exit(_, _) => "exit",
+            CallError::Abort(_) => "abort",
+            CallError::Exception(_, _) => "exception",
+        }
+    }
+
+    // the guest trapped or exited, its instance state can not be trusted
+    pub fn is_guest_fault(&self) -> bool {
+        return matches!(
+            self,
+            CallError::OutOfFuel(_, _)
+                | CallError::Timeout(_, _)
+                | CallError::Trap(_, _)
+                | CallError::Exit(_, _)
+                | CallError::Abort(_)
+                | CallError::Exception(_, _)
+        );
+    }
+
+    pub fn backtrace(&self) -> &[String] {
+        match self {
+            CallError::OutOfFuel(_, bt) => bt,
+            CallError::Timeout(_, bt) => bt,
+            CallError::Trap(_, bt) => bt,
+            CallError::Exit(_, bt) => bt,
+            CallError::Abort(bt) => bt,
+            CallError::Exception(_, bt) => bt,
+            _ => &[],
+        }
+    }
+
+    // {"$error": {"code", "message", "module", "usage", "backtrace"}}
+    pub fn to_smb(&self, wasm_path: &str, usage: &str) -> SmDtonBuffer {
+        let mut err = JsonValue::new_object();
+        err["code"] = self.code().into();
+        err["message"] = self.to_string().into();
+        err["module"] = wasm_path.into();
+        err["usage"] = usage.into();
+        if let CallError::Exception(th, _) = self {
+            err["type"] = th.type_name.as_str().into();
+            err["tinfo"] = th.tinfo.into();
+            if let Some(ref w) = th.what {
+                err["what"] = w.as_str().into();
+            }
+        }
+
+        let mut bt = JsonValue::new_array();
+        for x in self.backtrace() {
+            let _ = bt.push(x.as_str());
+        }
+        err["backtrace"] = bt;
+
+        let mut jsn = JsonValue::new_object();
+        jsn[ERROR] = err;
+        let mut sb = SmDtonBuilder::new_from_json(&jsn);
+        return sb.build();
+    }
+
+    pub fn from_trap(e: &wasmtime::Error) -> CallError {
+        match e.downcast_ref::<GuestExit>() {
+            Some(GuestExit::Exit(code)) => return CallError::Exit(*code, get_backtrace(e)),
+            Some(GuestExit::Abort) => return CallError::Abort(get_backtrace(e)),
+            None => {}
+        }
+        if let Some(th) = e.downcast_ref::<GuestThrow>() {
+            return CallError::Exception(th.clone(), get_backtrace(e));
+        }
+        // proc_exit of wasmtime-wasi
+        #[cfg(feature = "wasi")]
+        if let Some(ex) = e.downcast_ref::<I32Exit>() {
+            return CallError::Exit(ex.0, get_backtrace(e));
+        }
+        return CallError::Trap(format!("{:#}", e), get_backtrace(e));
+    }
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallError::Busy => write!(f, "all instances busy"),
+            CallError::OutOfFuel(n, _) => write!(f, "out of fuel --- budget {}", n),
+            CallError::Timeout(ms, _) => write!(f, "deadline exceeded --- {} ms", ms),
+            CallError::Trap(m, _) => write!(f, "trap --- {}", m),
+            CallError::Decode(m) => write!(f, "cannot decode --- {}", m),
+            CallError::Unavailable => write!(f, "instance unavailable"),
+            CallError::UnknownUsage(u) => write!(f, "unknown usage --- {}", u),
+            CallError::Exit(code, _) => write!(f, "guest exited --- {}", code),
+            CallError::Abort(_) => write!(f, "guest aborted"),
+            CallError::Exception(th, _) => write!(f, "{}", th),
+        }
+    }
+}
+
+impl std::error::Error for CallError {}
+
+// frames from the name section, innermost first
+pub fn get_backtrace(e: &wasmtime::Error) -> Vec<String> {
+    let mut frames: Vec<String> = Vec::new();
+    if let Some(bt) = e.downcast_ref::<WasmBacktrace>() {
+        for (i, fr) in bt.frames().iter().enumerate() {
+            let module = fr.module().name().unwrap_or("<module>");
+            let name = match fr.func_name() {
+                Some(n) => n.to_string(),
+                None => format!("<wasm function {}>", fr.func_index()),
+            };
+            match fr.module_offset() {
+                Some(off) => frames.push(format!("#{} {}!{} @ 0x{:x}", i, module, name, off)),
+                None => frames.push(format!("#{} {}!{}", i, module, name)),
+            }
+        }
+    }
+    return frames;
+}
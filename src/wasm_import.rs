@@ -1,42 +1,59 @@
 use smdton::SmDtonBuilder;
 use wasmtime::*;
+#[cfg(feature = "wasi")]
+use wasmtime_wasi::p1::WasiP1Ctx;
 
 use lazy_static::lazy_static;
 use smcore::{smh, smu};
 
-use crate::wasm::WS_INA;
-use crate::wasm_util::{MAX_STORE, WS_UTL};
+use crate::wasm::WS_ENV;
+use crate::wasm_args::{self, WasmArgs};
+use crate::wasm_det::WasmDet;
+use crate::wasm_error::GuestExit;
+use crate::wasm_limit::WasmLimiter;
+use crate::wasm_output::WasmOutput;
+use crate::wasm_util::WS_UTL;
 
-const IMP_REPEAT_VALUE: Option<WasmImportSupport> = None;
+// wasi errno
+pub const ERRNO_BADF: i32 = 8;
+pub const ERRNO_FAULT: i32 = 21;
+pub const ERRNO_IO: i32 = 29;
 
 lazy_static! {
-    pub static ref WS_IMP: [Option<WasmImportSupport>; MAX_STORE] = {
-        let mut arr: [Option<WasmImportSupport>; MAX_STORE] = [IMP_REPEAT_VALUE; MAX_STORE];
-        for i in 0..MAX_STORE {
-            arr[i] = Some(WasmImportSupport::new(i));
-        }
-        arr
-    };
+    pub static ref WS_IMP: WasmImportSupport = WasmImportSupport::new();
 }
 
-pub struct WasmState {}
-
-pub struct WasmImportSupport {
+pub struct WasmState {
     pub sn: usize,
+    pub limiter: WasmLimiter,
+    // exchange memory of the instance, set by WasmInstance::init
+    pub memory: Option<Memory>,
+    // wasmtime-wasi context when WasmConfig::wasi
+    #[cfg(feature = "wasi")]
+    pub wasi: Option<WasiP1Ctx>,
+    // line buffers for fd_write to stdout and stderr
+    pub output: WasmOutput,
+    // clock and random_get source
+    pub det: WasmDet,
+    // argv and environ from WasmConfig
+    pub args: WasmArgs,
 }
 
+pub struct WasmImportSupport {}
+
 impl WasmImportSupport {
-    pub fn new(sn: usize) -> WasmImportSupport {
-        let obj = WasmImportSupport { sn: sn };
+    pub fn new() -> WasmImportSupport {
+        let obj = WasmImportSupport {};
         obj
     }
 
-    pub fn hostdebug(&self, _d1: i32, _d2: i32) {
-        println!("+++ {} --- < < --- {} --- {} ---", self.sn, _d1, _d2);
+    pub fn hostdebug(&self, _caller: Caller<'_, WasmState>, _d1: i32, _d2: i32) {
+        let sn = _caller.data().sn;
+        smu.log(&format!("+++ {} --- < < --- {} --- {} ---", sn, _d1, _d2));
     }
 
-    pub fn hostgetms(&self) -> i64 {
-        return smu.get_current_ms() as i64;
+    pub fn hostgetms(&self, _caller: Caller<'_, WasmState>) -> i64 {
+        return _caller.data().det.get_ms() as i64;
     }
 
     pub fn hostputmemory(&self, mut _caller: Caller<'_, WasmState>, ptr: usize, ty: i32) {
@@ -44,42 +61,52 @@ impl WasmImportSupport {
             return;
         }
 
-        let mr = _caller.get_export("memory").unwrap();
-        let mem = mr.into_memory().unwrap();
-        let txt = WS_UTL.get_buffer_text(_caller.as_context_mut(), mem, ptr);
-        println!("+++ {} {}", self.sn, txt);
+        let mem = match _caller.data().memory {
+            Some(m) => m,
+            None => return,
+        };
+        if let Some(txt) = WS_UTL.get_buffer_text(_caller.as_context_mut(), mem, ptr) {
+            smu.log(&format!("+++ {} {}", _caller.data().sn, txt));
+        }
     }
 
     pub fn hostcallsm(&self, mut _caller: Caller<'_, WasmState>, ptr: usize) -> i32 {
-        let mr = _caller.get_export("memory").unwrap();
-        let mem = mr.into_memory().unwrap();
-        if WS_UTL.is_json(self.sn) {
-            let calltxt = WS_UTL.get_buffer_text(_caller.as_context_mut(), mem, ptr);
+        let sn = _caller.data().sn;
+        let mem = match _caller.data().memory {
+            Some(m) => m,
+            None => return 0,
+        };
+        if WS_UTL.is_json(sn) {
+            let calltxt = WS_UTL
+                .get_buffer_text(_caller.as_context_mut(), mem, ptr)
+                .unwrap_or_default();
 
             if calltxt.len() > 0 {
-                let callobj = json::parse(&calltxt).unwrap();
-                let usage = smu.get_string(&callobj, "$usage").unwrap();
+                let callobj = match json::parse(&calltxt) {
+                    Ok(callobj) => callobj,
+                    Err(_) => return 0,
+                };
+                let usage = smu.get_string(&callobj, "$usage").unwrap_or_default();
                 let mut sb = SmDtonBuilder::new_from_json(&callobj);
                 let _ret = smh.call(sb.build());
 
-                if let Some(a) = WS_INA.get(self.sn) {
-                    if let Some(inst) = a {
-                        let ptr = inst.output_memory(_caller.as_context_mut(), &usage, &_ret);
-                        return ptr;
-                    }
+                if let Some(inst) = WS_ENV.get_ina(sn) {
+                    let ptr = inst.output_memory(_caller.as_context_mut(), &usage, &_ret);
+                    return ptr.unwrap_or(0);
                 }
             }
         } else {
-            let (name, smb) = WS_UTL.get_buffer_smb(_caller.as_context_mut(), mem, ptr);
+            let (name, smb) = match WS_UTL.get_buffer_smb(_caller.as_context_mut(), mem, ptr) {
+                Some(x) => x,
+                None => return 0,
+            };
 
             if smb.buf.len() > 0 {
                 let ret = smh.call(smb);
 
-                if let Some(a) = WS_INA.get(self.sn) {
-                    if let Some(inst) = a {
-                        let ptr = inst.output_memory(_caller.as_context_mut(), &name, &ret);
-                        return ptr;
-                    }
+                if let Some(inst) = WS_ENV.get_ina(sn) {
+                    let ptr = inst.output_memory(_caller.as_context_mut(), &name, &ret);
+                    return ptr.unwrap_or(0);
                 }
             }
         }
@@ -93,18 +120,98 @@ impl WasmImportSupport {
         _p2: i64,
         _p3: i32,
     ) -> i32 {
-        let nsec = smu.get_current_ms() * 1000 * 1000;
+        let nsec = _caller.data().det.get_ms() * 1000 * 1000;
         let bytes: [u8; 8] = (nsec as i64).to_le_bytes();
 
-        let mr = _caller.get_export("memory").unwrap();
-        let mem = mr.into_memory().unwrap();
-        mem.write(_caller, _p3 as usize, &bytes).unwrap();
+        let mem = match _caller.data().memory {
+            Some(m) => m,
+            None => return ERRNO_FAULT,
+        };
+        if mem.write(_caller, _p3 as usize, &bytes).is_err() {
+            return ERRNO_FAULT;
+        }
 
         return 0;
     }
 
-    pub fn f_i_o(&self, _caller: Caller<'_, WasmState>) {
-        smu.log(&format!("--- host func --- in --- out ---"));
+    // iovec array of (buf u32, len u32), only stdout and stderr are writable
+    pub fn fd_write(
+        &self,
+        mut _caller: Caller<'_, WasmState>,
+        fd: i32,
+        iovs: i32,
+        iovs_len: i32,
+        nwritten: i32,
+    ) -> i32 {
+        if fd != 1 && fd != 2 {
+            return ERRNO_BADF;
+        }
+        let mem = match _caller.data().memory {
+            Some(m) => m,
+            None => return ERRNO_FAULT,
+        };
+
+        // nothing larger than the memory can be read, check before allocating
+        let size = mem.data_size(&_caller);
+        let n = iovs_len as u32 as usize * 8;
+        if n > size {
+            return ERRNO_FAULT;
+        }
+
+        let mut vec: Vec<u8> = vec![0; n];
+        if mem.read(&_caller, iovs as u32 as usize, &mut vec).is_err() {
+            return ERRNO_FAULT;
+        }
+
+        let mut data: Vec<u8> = Vec::new();
+        for iov in vec.chunks_exact(8) {
+            let ptr = u32::from_le_bytes(iov[0..4].try_into().unwrap()) as usize;
+            let len = u32::from_le_bytes(iov[4..8].try_into().unwrap()) as usize;
+            let end = data.len();
+            if end + len > size {
+                return ERRNO_FAULT;
+            }
+            data.resize(end + len, 0);
+            if mem.read(&_caller, ptr, &mut data[end..]).is_err() {
+                return ERRNO_FAULT;
+            }
+        }
+
+        let bytes = (data.len() as u32).to_le_bytes();
+        if mem
+            .write(&mut _caller, nwritten as u32 as usize, &bytes)
+            .is_err()
+        {
+            return ERRNO_FAULT;
+        }
+        _caller.data_mut().output.write(fd as usize, &data);
+        return 0;
+    }
+
+    pub fn args_sizes_get(&self, _caller: Caller<'_, WasmState>, argc: i32, size: i32) -> i32 {
+        return wasm_args::sizes_get(_caller, false, argc, size);
+    }
+
+    pub fn args_get(&self, _caller: Caller<'_, WasmState>, argv: i32, buf: i32) -> i32 {
+        return wasm_args::get(_caller, false, argv, buf);
+    }
+
+    pub fn environ_sizes_get(&self, _caller: Caller<'_, WasmState>, count: i32, size: i32) -> i32 {
+        return wasm_args::sizes_get(_caller, true, count, size);
+    }
+
+    pub fn environ_get(&self, _caller: Caller<'_, WasmState>, environ: i32, buf: i32) -> i32 {
+        return wasm_args::get(_caller, true, environ, buf);
+    }
+
+    pub fn proc_exit(&self, mut _caller: Caller<'_, WasmState>, code: i32) -> Result<()> {
+        // what the guest printed before exiting is not lost
+        _caller.data_mut().output.flush();
+        return Err(Error::new(GuestExit::Exit(code)));
+    }
+
+    pub fn abort(&self, _caller: Caller<'_, WasmState>) -> Result<()> {
+        return Err(Error::new(GuestExit::Abort));
     }
 
     pub fn f_i_o_i4(&self, _caller: Caller<'_, WasmState>) -> i32 {
@@ -112,9 +219,27 @@ impl WasmImportSupport {
         return 0;
     }
 
-    pub fn f_i_o_f8(&self, _caller: Caller<'_, WasmState>) -> f64 {
-        smu.log(&format!("--- host func --- in --- out i64 ---"));
-        return 0.0;
+    pub fn emscripten_date_now(&self, _caller: Caller<'_, WasmState>) -> f64 {
+        return _caller.data().det.get_ms() as f64;
+    }
+
+    pub fn random_get(&self, mut _caller: Caller<'_, WasmState>, buf: i32, len: i32) -> i32 {
+        let mem = match _caller.data().memory {
+            Some(m) => m,
+            None => return ERRNO_FAULT,
+        };
+        let len = len as u32 as usize;
+        if len > mem.data_size(&_caller) {
+            return ERRNO_FAULT;
+        }
+        let mut vec: Vec<u8> = vec![0; len];
+        if !_caller.data_mut().det.fill(&mut vec) {
+            return ERRNO_IO;
+        }
+        if mem.write(&mut _caller, buf as u32 as usize, &vec).is_err() {
+            return ERRNO_FAULT;
+        }
+        return 0;
     }
 
     pub fn f_i_i4_o(&self, _caller: Caller<'_, WasmState>, _p1: i32) {
@@ -137,13 +262,6 @@ impl WasmImportSupport {
         return 0;
     }
 
-    pub fn f_i_i4_3_o(&self, _caller: Caller<'_, WasmState>, _p1: i32, _p2: i32, _p3: i32) {
-        smu.log(&format!(
-            "--- host func --- in i32 i32 i32 --- out --- {} ---",
-            _p1
-        ));
-    }
-
     pub fn f_i_i4_3_o_i4(
         &self,
         _caller: Caller<'_, WasmState>,
@@ -189,43 +307,6 @@ impl WasmImportSupport {
         return 0;
     }
 
-    pub fn f_i_i4_7_o_i4(
-        &self,
-        _caller: Caller<'_, WasmState>,
-        _p1: i32,
-        _p2: i32,
-        _p3: i32,
-        _p4: i32,
-        _p5: i32,
-        _p6: i32,
-        _p7: i32,
-    ) -> i32 {
-        smu.log(&format!(
-            "--- host func --- in i32 i32 i32 i32 i32 i32 i32 --- out i32 --- {} ---",
-            _p1
-        ));
-        return 0;
-    }
-
-    pub fn f_i_i4_8_o_i4(
-        &self,
-        _caller: Caller<'_, WasmState>,
-        _p1: i32,
-        _p2: i32,
-        _p3: i32,
-        _p4: i32,
-        _p5: i32,
-        _p6: i32,
-        _p7: i32,
-        _p8: i32,
-    ) -> i32 {
-        smu.log(&format!(
-            "--- host func --- in i32 i32 i32 i32 i32 i32 i32 --- out i32 --- {} ---",
-            _p1
-        ));
-        return 0;
-    }
-
     pub fn f_i_i4_i8_i4_i4_o_i4(
         &self,
         _caller: Caller<'_, WasmState>,
@@ -0,0 +1,76 @@
+use smcore::smu;
+use wasmtime::*;
+
+use crate::wasm_config::WasmConfig;
+use crate::wasm_stat;
+
+const PAGE_SIZE: usize = 0x10000;
+
+pub struct WasmLimiter {
+    path: String,
+    max_page: Option<usize>,
+    max_table: Option<usize>,
+    max_instance: Option<usize>,
+}
+
+impl WasmLimiter {
+    pub fn new() -> WasmLimiter {
+        WasmLimiter {
+            path: String::new(),
+            max_page: None,
+            max_table: None,
+            max_instance: None,
+        }
+    }
+
+    pub fn set(&mut self, wasm_path: &str, cfg: &WasmConfig) {
+        self.path = wasm_path.to_string();
+        self.max_page = cfg.max_page;
+        self.max_table = cfg.max_table;
+        self.max_instance = cfg.max_instance;
+    }
+
+    fn refuse(&self, what: &str, current: usize, desired: usize) {
+        smu.log(&format!(
+            "--- {} --- {} growth refused --- {} -> {} ---",
+            self.path, what, current, desired
+        ));
+        wasm_stat::add_refused(&self.path);
+    }
+}
+
+impl ResourceLimiter for WasmLimiter {
+    fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> Result<bool> {
+        if let Some(mp) = self.max_page {
+            if desired > mp.saturating_mul(PAGE_SIZE) {
+                self.refuse("memory", current / PAGE_SIZE, desired.div_ceil(PAGE_SIZE));
+                return Ok(false);
+            }
+        }
+        return Ok(true);
+    }
+
+    fn table_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> Result<bool> {
+        if let Some(mt) = self.max_table {
+            if desired > mt {
+                self.refuse("table", current, desired);
+                return Ok(false);
+            }
+        }
+        return Ok(true);
+    }
+
+    fn instances(&self) -> usize {
+        return self.max_instance.unwrap_or(DEFAULT_INSTANCE_LIMIT);
+    }
+}
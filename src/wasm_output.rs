@@ -0,0 +1,88 @@
+use smcore::smu;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+
+use crate::wasm_config::WasmConfig;
+
+// a line longer than this is cut, so a guest without newlines can not grow it forever
+const LINE_MAX: usize = 64 * 1024;
+
+// stdout and stderr of one instance, split into lines
+pub struct WasmOutput {
+    path: String,
+    sn: usize,
+    file: Option<File>,
+    buf: [Vec<u8>; 2],
+}
+
+impl WasmOutput {
+    pub fn new(sn: usize) -> WasmOutput {
+        WasmOutput {
+            path: String::new(),
+            sn: sn,
+            file: None,
+            buf: [Vec::new(), Vec::new()],
+        }
+    }
+
+    pub fn set(&mut self, wasm_path: &str, cfg: &WasmConfig) {
+        self.path = wasm_path.to_string();
+        self.file = None;
+        if let Some(ref p) = cfg.log_file {
+            match OpenOptions::new().create(true).append(true).open(p) {
+                Ok(f) => self.file = Some(f),
+                Err(e) => smu.log(&format!(
+                    "--- wasm log file --- {} --- {} --- {} ---",
+                    wasm_path,
+                    p.display(),
+                    e
+                )),
+            }
+        }
+    }
+
+    // fd 1 or 2
+    pub fn write(&mut self, fd: usize, data: &[u8]) {
+        let i = fd - 1;
+        self.buf[i].extend_from_slice(data);
+        while let Some(pos) = self.buf[i].iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf[i].drain(..=pos).collect();
+            self.emit(fd, &line[..pos]);
+        }
+        if self.buf[i].len() > LINE_MAX {
+            let line = std::mem::take(&mut self.buf[i]);
+            self.emit(fd, &line);
+        }
+    }
+
+    pub fn flush(&mut self) {
+        for i in 0..2 {
+            if !self.buf[i].is_empty() {
+                let line = std::mem::take(&mut self.buf[i]);
+                self.emit(i + 1, &line);
+            }
+        }
+    }
+
+    fn emit(&mut self, fd: usize, line: &[u8]) {
+        let txt = String::from_utf8_lossy(line);
+        let txt = txt.strip_suffix('\r').unwrap_or(&txt);
+        let tag = if fd == 1 { "stdout" } else { "stderr" };
+        if let Some(ref mut f) = self.file {
+            if writeln!(f, "{} {} {}", self.sn, tag, txt).is_ok() {
+                return;
+            }
+        }
+        smu.log(&format!(
+            "--- {} --- {} --- {} --- {}",
+            self.path, self.sn, tag, txt
+        ));
+    }
+}
+
+impl Drop for WasmOutput {
+    // a restarted or dropped instance keeps its last partial line
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+
+use lazy_static::lazy_static;
+
+use crate::wasm::WasmInstanceStub;
+
+lazy_static! {
+    // primary sn to the pool of instances of the same module
+    pub static ref WS_POL: RwLock<HashMap<usize, Arc<WasmPool>>> = RwLock::new(HashMap::new());
+}
+
+struct WasmPoolState {
+    free: Vec<usize>,
+    waiting: usize,
+}
+
+pub struct WasmPool {
+    pub sns: Vec<usize>,
+    // held by the pool, a dropped slot number may already serve another module
+    ins: Vec<Arc<WasmInstanceStub>>,
+    queue: usize,
+    st: Mutex<WasmPoolState>,
+    cv: Condvar,
+}
+
+impl WasmPool {
+    pub fn new(sns: Vec<usize>, ins: Vec<Arc<WasmInstanceStub>>, queue: usize) -> WasmPool {
+        WasmPool {
+            st: Mutex::new(WasmPoolState {
+                free: (0..sns.len()).collect(),
+                waiting: 0,
+            }),
+            sns: sns,
+            ins: ins,
+            queue: queue,
+            cv: Condvar::new(),
+        }
+    }
+
+    // index and instance, None when every instance is busy and the wait queue is full
+    pub fn acquire(&self) -> Option<(usize, Arc<WasmInstanceStub>)> {
+        let mut st = self.st.lock().unwrap();
+        if st.free.is_empty() {
+            if st.waiting >= self.queue {
+                return None;
+            }
+            st.waiting += 1;
+            while st.free.is_empty() {
+                st = self.cv.wait(st).unwrap();
+            }
+            st.waiting -= 1;
+        }
+        let idx = st.free.pop()?;
+        return Some((idx, self.ins[idx].clone()));
+    }
+
+    pub fn release(&self, idx: usize) {
+        let mut st = self.st.lock().unwrap();
+        st.free.push(idx);
+        self.cv.notify_one();
+    }
+}
@@ -0,0 +1,103 @@
+use json::JsonValue;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+lazy_static! {
+    // module path to its counters
+    pub static ref WS_STA: RwLock<HashMap<String, WasmStat>> = RwLock::new(HashMap::new());
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct UsageStat {
+    pub calls: u64,
+    pub fuel: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct WasmStat {
+    pub calls: u64,
+    pub fuel: u64,
+    pub out_of_fuel: u64,
+    pub timeout: u64,
+    // memory or table growth refused by the limiter
+    pub refused: u64,
+    // instances dropped after a trap and instantiated again
+    pub restarts: u64,
+    // restarts that failed, the next call tries again
+    pub restart_failed: u64,
+    pub usage: HashMap<String, UsageStat>,
+}
+
+impl WasmStat {
+    pub fn to_json(&self) -> JsonValue {
+        let mut jsn = JsonValue::new_object();
+        jsn["calls"] = self.calls.into();
+        jsn["fuel"] = self.fuel.into();
+        jsn["out_of_fuel"] = self.out_of_fuel.into();
+        jsn["timeout"] = self.timeout.into();
+        jsn["refused"] = self.refused.into();
+        jsn["restarts"] = self.restarts.into();
+        jsn["restart_failed"] = self.restart_failed.into();
+
+        let mut usage = JsonValue::new_object();
+        for (name, u) in self.usage.iter() {
+            let mut x = JsonValue::new_object();
+            x["calls"] = u.calls.into();
+            x["fuel"] = u.fuel.into();
+            usage[name.as_str()] = x;
+        }
+        jsn["usage"] = usage;
+        return jsn;
+    }
+}
+
+pub fn add_call(wasm_path: &str, usage: &str, fuel: u64) {
+    let mut map = WS_STA.write().unwrap();
+    let st = map.entry(wasm_path.to_string()).or_default();
+    st.calls += 1;
+    st.fuel += fuel;
+
+    let u = st.usage.entry(usage.to_string()).or_default();
+    u.calls += 1;
+    u.fuel += fuel;
+}
+
+pub fn add_out_of_fuel(wasm_path: &str) {
+    let mut map = WS_STA.write().unwrap();
+    let st = map.entry(wasm_path.to_string()).or_default();
+    st.out_of_fuel += 1;
+}
+
+pub fn add_timeout(wasm_path: &str) {
+    let mut map = WS_STA.write().unwrap();
+    let st = map.entry(wasm_path.to_string()).or_default();
+    st.timeout += 1;
+}
+
+pub fn add_refused(wasm_path: &str) {
+    let mut map = WS_STA.write().unwrap();
+    let st = map.entry(wasm_path.to_string()).or_default();
+    st.refused += 1;
+}
+
+pub fn add_restart(wasm_path: &str) {
+    let mut map = WS_STA.write().unwrap();
+    let st = map.entry(wasm_path.to_string()).or_default();
+    st.restarts += 1;
+}
+
+pub fn add_restart_failed(wasm_path: &str) {
+    let mut map = WS_STA.write().unwrap();
+    let st = map.entry(wasm_path.to_string()).or_default();
+    st.restart_failed += 1;
+}
+
+pub fn get_stats() -> JsonValue {
+    let map = WS_STA.read().unwrap();
+    let mut jsn = JsonValue::new_object();
+    for (path, st) in map.iter() {
+        jsn[path.as_str()] = st.to_json();
+    }
+    return jsn;
+}
@@ -1,22 +1,18 @@
 use std::sync::Mutex;
 use wasmtime::*;
 
-use lazy_static::lazy_static;
-
-use crate::wasm_import::{WasmState, WS_IMP};
-use crate::wasm_util::{MAX_STORE, WS_UTL};
-
-const STO_REPEAT_VALUE: Option<WasmStoreStub> = None;
-
-lazy_static! {
-    pub static ref WS_STO: [Option<WasmStoreStub>; MAX_STORE] = {
-        let mut arr: [Option<WasmStoreStub>; MAX_STORE] = [STO_REPEAT_VALUE; MAX_STORE];
-        for i in 0..MAX_STORE {
-            arr[i] = Some(WasmStoreStub::new(i));
-        }
-        arr
-    };
-}
+use crate::wasm_args::WasmArgs;
+use crate::wasm_config::WasmConfig;
+use crate::wasm_det::WasmDet;
+use crate::wasm_emsc;
+use crate::wasm_error::LoadError;
+use crate::wasm_import::{WS_IMP, WasmState};
+use crate::wasm_limit::WasmLimiter;
+use crate::wasm_output::WasmOutput;
+use crate::wasm_util::{EPOCH_NEVER, WS_UTL};
+use crate::wasm_valid::get_signature;
+#[cfg(feature = "wasi")]
+use crate::wasm_wasi;
 
 pub struct WasmStoreStub {
     sn: usize,
@@ -25,25 +21,27 @@ pub struct WasmStoreStub {
 }
 
 impl WasmStoreStub {
-    fn new(id: usize) -> Self {
-        let wsta = WasmState {};
+    pub fn new(id: usize, _cfg: &WasmConfig, module: &Module) -> Self {
+        let wimp: &'static _ = &*WS_IMP;
 
-        let wimp = WS_IMP[id].as_ref().unwrap();
-
-        let mut _store = Store::new(&WS_UTL.engine, wsta);
+        let _store = Self::_new_store(id);
 
         let mut lnk: Linker<WasmState> = Linker::new(&WS_UTL.engine);
 
         //-------- host impl --------
         // for debug
-        lnk.func_wrap("env", "hostdebug", |_d0: i32, _d1: i32| {
-            wimp.hostdebug(_d0, _d1)
-        })
+        lnk.func_wrap(
+            "env",
+            "hostdebug",
+            |_caller: Caller<'_, WasmState>, _d0: i32, _d1: i32| wimp.hostdebug(_caller, _d0, _d1),
+        )
         .unwrap();
 
         // for ms
-        lnk.func_wrap("env", "hostgetms", || wimp.hostgetms())
-            .unwrap();
+        lnk.func_wrap("env", "hostgetms", |_caller: Caller<'_, WasmState>| {
+            wimp.hostgetms(_caller)
+        })
+        .unwrap();
 
         // for memory
         lnk.func_wrap(
@@ -85,7 +83,7 @@ impl WasmStoreStub {
         lnk.func_wrap(
             "wasi_snapshot_preview1",
             "proc_exit",
-            |_caller: Caller<'_, WasmState>, _p1: i32| wimp.f_i_i4_o(_caller, 2),
+            |_caller: Caller<'_, WasmState>, _p1: i32| wimp.proc_exit(_caller, _p1),
         )
         .unwrap();
 
@@ -93,7 +91,7 @@ impl WasmStoreStub {
             "wasi_snapshot_preview1",
             "environ_sizes_get",
             |_caller: Caller<'_, WasmState>, _p1: i32, _p2: i32| {
-                wimp.f_i_i4_2_o_i4(_caller, 1, _p2)
+                wimp.environ_sizes_get(_caller, _p1, _p2)
             },
         )
         .unwrap();
@@ -102,7 +100,7 @@ impl WasmStoreStub {
             "wasi_snapshot_preview1",
             "environ_get",
             |_caller: Caller<'_, WasmState>, _p1: i32, _p2: i32| {
-                wimp.f_i_i4_2_o_i4(_caller, 2, _p2)
+                wimp.environ_get(_caller, _p1, _p2)
             },
         )
         .unwrap();
@@ -111,7 +109,7 @@ impl WasmStoreStub {
             "wasi_snapshot_preview1",
             "fd_write",
             |_caller: Caller<'_, WasmState>, _p1: i32, _p2: i32, _p3: i32, _p4: i32| {
-                wimp.f_i_i4_4_o_i4(_caller, 1, _p2, _p3, _p4)
+                wimp.fd_write(_caller, _p1, _p2, _p3, _p4)
             },
         )
         .unwrap();
@@ -142,9 +140,7 @@ impl WasmStoreStub {
         lnk.func_wrap(
             "wasi_snapshot_preview1",
             "args_get",
-            |_caller: Caller<'_, WasmState>, _p1: i32, _p2: i32| {
-                wimp.f_i_i4_2_o_i4(_caller, 3, _p2)
-            },
+            |_caller: Caller<'_, WasmState>, _p1: i32, _p2: i32| wimp.args_get(_caller, _p1, _p2),
         )
         .unwrap();
 
@@ -152,7 +148,7 @@ impl WasmStoreStub {
             "wasi_snapshot_preview1",
             "args_sizes_get",
             |_caller: Caller<'_, WasmState>, _p1: i32, _p2: i32| {
-                wimp.f_i_i4_2_o_i4(_caller, 4, _p2)
+                wimp.args_sizes_get(_caller, _p1, _p2)
             },
         )
         .unwrap();
@@ -160,9 +156,7 @@ impl WasmStoreStub {
         lnk.func_wrap(
             "wasi_snapshot_preview1",
             "random_get",
-            |_caller: Caller<'_, WasmState>, _p1: i32, _p2: i32| {
-                wimp.f_i_i4_2_o_i4(_caller, 5, _p2)
-            },
+            |_caller: Caller<'_, WasmState>, _p1: i32, _p2: i32| wimp.random_get(_caller, _p1, _p2),
         )
         .unwrap();
 
@@ -175,33 +169,61 @@ impl WasmStoreStub {
         )
         .unwrap();
 
-        // for wasi
-
+        // older builds of our plugins import emscripten under this name
+        Self::add_emscripten(&mut lnk, "wasi_snapshot_preview1");
         lnk.func_wrap(
             "wasi_snapshot_preview1",
+            "emscripten_notify_memory_growth",
+            |_caller: Caller<'_, WasmState>, _p1: i32| wimp.f_i_i4_o_i4(_caller, 3),
+        )
+        .unwrap();
+
+        // emscripten itself imports from env, only linked when the module asks,
+        // names like abort mean something else to other toolchains
+        if wasm_emsc::is_emscripten(module) {
+            Self::add_emscripten(&mut lnk, "env");
+        }
+
+        #[cfg(feature = "wasi")]
+        if _cfg.wasi {
+            wasm_wasi::add_to_linker(&mut lnk);
+        }
+
+        let ct = Mutex::new(_store);
+
+        WasmStoreStub {
+            sn: id,
+            lnk: lnk,
+            st: ct,
+        }
+    }
+
+    fn add_emscripten(lnk: &mut Linker<WasmState>, md: &'static str) {
+        let wimp: &'static _ = &*WS_IMP;
+
+        lnk.func_wrap(
+            md,
             "emscripten_resize_heap",
-            |_caller: Caller<'_, WasmState>, _p1: i32| wimp.f_i_i4_o_i4(_caller, 2),
+            |_caller: Caller<'_, WasmState>, _p1: i32| wasm_emsc::resize_heap(_caller, _p1),
         )
         .unwrap();
 
         lnk.func_wrap(
-            "wasi_snapshot_preview1",
+            md,
             "emscripten_memcpy_js",
             |_caller: Caller<'_, WasmState>, _p1: i32, _p2: i32, _p3: i32| {
-                wimp.f_i_i4_3_o(_caller, 1, _p2, _p3)
+                wasm_emsc::memcpy_js(_caller, _p1, _p2, _p3)
             },
         )
         .unwrap();
 
-        lnk.func_wrap(
-            "wasi_snapshot_preview1",
-            "abort",
-            |_caller: Caller<'_, WasmState>| wimp.f_i_o(_caller),
-        )
+        lnk.func_wrap(md, "abort", |_caller: Caller<'_, WasmState>| {
+            wimp.abort(_caller)
+        })
         .unwrap();
 
         lnk.func_wrap(
-            "wasi_snapshot_preview1",
+            md,
             "strftime_l",
             |_caller: Caller<'_, WasmState>, _p1: i32, _p2: i32, _p3: i32, _p4: i32, _p5: i32| {
                 wimp.f_i_i4_5_o_i4(_caller, 1, _p2, _p3, _p4, _p5)
@@ -210,30 +232,23 @@ impl WasmStoreStub {
         .unwrap();
 
         lnk.func_wrap(
-            "wasi_snapshot_preview1",
-            "emscripten_notify_memory_growth",
-            |_caller: Caller<'_, WasmState>, _p1: i32| wimp.f_i_i4_o_i4(_caller, 3),
-        )
-        .unwrap();
-
-        lnk.func_wrap(
-            "wasi_snapshot_preview1",
+            md,
             "__cxa_throw",
             |_caller: Caller<'_, WasmState>, _p1: i32, _p2: i32, _p3: i32| {
-                wimp.f_i_i4_3_o(_caller, 2, _p2, _p3)
+                wasm_emsc::cxa_throw(_caller, _p1, _p2)
             },
         )
         .unwrap();
 
         lnk.func_wrap(
-            "wasi_snapshot_preview1",
+            md,
             "emscripten_date_now",
-            |_caller: Caller<'_, WasmState>| wimp.f_i_o_f8(_caller),
+            |_caller: Caller<'_, WasmState>| wimp.emscripten_date_now(_caller),
         )
         .unwrap();
 
         lnk.func_wrap(
-            "wasi_snapshot_preview1",
+            md,
             "__syscall_openat",
             |_caller: Caller<'_, WasmState>, _p1: i32, _p2: i32, _p3: i32, _p4: i32| {
                 wimp.f_i_i4_4_o_i4(_caller, 3, _p2, _p3, _p4)
@@ -242,7 +257,7 @@ impl WasmStoreStub {
         .unwrap();
 
         lnk.func_wrap(
-            "wasi_snapshot_preview1",
+            md,
             "__syscall_fstat64",
             |_caller: Caller<'_, WasmState>, _p1: i32, _p2: i32| {
                 wimp.f_i_i4_2_o_i4(_caller, 6, _p2)
@@ -251,7 +266,7 @@ impl WasmStoreStub {
         .unwrap();
 
         lnk.func_wrap(
-            "wasi_snapshot_preview1",
+            md,
             "__syscall_stat64",
             |_caller: Caller<'_, WasmState>, _p1: i32, _p2: i32| {
                 wimp.f_i_i4_2_o_i4(_caller, 7, _p2)
@@ -260,7 +275,7 @@ impl WasmStoreStub {
         .unwrap();
 
         lnk.func_wrap(
-            "wasi_snapshot_preview1",
+            md,
             "__syscall_newfstatat",
             |_caller: Caller<'_, WasmState>, _p1: i32, _p2: i32, _p3: i32, _p4: i32| {
                 wimp.f_i_i4_4_o_i4(_caller, 4, _p2, _p3, _p4)
@@ -269,7 +284,7 @@ impl WasmStoreStub {
         .unwrap();
 
         lnk.func_wrap(
-            "wasi_snapshot_preview1",
+            md,
             "__syscall_lstat64",
             |_caller: Caller<'_, WasmState>, _p1: i32, _p2: i32| {
                 wimp.f_i_i4_2_o_i4(_caller, 8, _p2)
@@ -278,7 +293,7 @@ impl WasmStoreStub {
         .unwrap();
 
         lnk.func_wrap(
-            "wasi_snapshot_preview1",
+            md,
             "__syscall_fcntl64",
             |_caller: Caller<'_, WasmState>, _p1: i32, _p2: i32, _p3: i32| {
                 wimp.f_i_i4_3_o_i4(_caller, 1, _p2, _p3)
@@ -287,7 +302,7 @@ impl WasmStoreStub {
         .unwrap();
 
         lnk.func_wrap(
-            "wasi_snapshot_preview1",
+            md,
             "__syscall_ioctl",
             |_caller: Caller<'_, WasmState>, _p1: i32, _p2: i32, _p3: i32| {
                 wimp.f_i_i4_3_o_i4(_caller, 2, _p2, _p3)
@@ -296,25 +311,25 @@ impl WasmStoreStub {
         .unwrap();
 
         lnk.func_wrap(
-            "wasi_snapshot_preview1",
+            md,
             "_tzset_js",
             |_caller: Caller<'_, WasmState>, _p1: i32, _p2: i32, _p3: i32| {
-                wimp.f_i_i4_3_o(_caller, 3, _p2, _p3)
+                wasm_emsc::tzset_js(_caller, _p1, _p2, _p3)
             },
         )
         .unwrap();
 
         lnk.func_wrap(
-            "wasi_snapshot_preview1",
+            md,
             "_localtime_js",
             |_caller: Caller<'_, WasmState>, _p1: i32, _p2: i32, _p3: i32| {
-                wimp.f_i_i4_3_o(_caller, 4, _p2, _p3)
+                wasm_emsc::localtime_js(_caller, _p1, _p2, _p3)
             },
         )
         .unwrap();
 
         lnk.func_wrap(
-            "wasi_snapshot_preview1",
+            md,
             "_munmap_js",
             |_caller: Caller<'_, WasmState>,
              _p1: i32,
@@ -323,12 +338,12 @@ impl WasmStoreStub {
              _p4: i32,
              _p5: i32,
              _p6: i32,
-             _p7: i32| { wimp.f_i_i4_7_o_i4(_caller, 1, _p2, _p3, _p4, _p5, _p6, _p7) },
+             _p7: i32| { wasm_emsc::munmap_js(_caller) },
         )
         .unwrap();
 
         lnk.func_wrap(
-            "wasi_snapshot_preview1",
+            md,
             "_mmap_js",
             |_caller: Caller<'_, WasmState>,
              _p1: i32,
@@ -338,27 +353,87 @@ impl WasmStoreStub {
              _p5: i32,
              _p6: i32,
              _p7: i32,
-             _p8: i32| {
-                wimp.f_i_i4_8_o_i4(_caller, 1, _p2, _p3, _p4, _p5, _p6, _p7, _p8)
-            },
+             _p8: i32| { wasm_emsc::mmap_js(_caller) },
         )
         .unwrap();
+    }
 
-        let ct = Mutex::new(_store);
-
-        WasmStoreStub {
+    fn _new_store(id: usize) -> Store<WasmState> {
+        let wsta = WasmState {
             sn: id,
-            lnk: lnk,
-            st: ct,
+            limiter: WasmLimiter::new(),
+            memory: None,
+            #[cfg(feature = "wasi")]
+            wasi: None,
+            output: WasmOutput::new(id),
+            det: WasmDet::new(),
+            args: WasmArgs::new(),
+        };
+
+        let mut _store = Store::new(&WS_UTL.engine, wsta);
+        _store.limiter(|s| &mut s.limiter);
+        if WS_UTL.fuel {
+            // unmetered until a call sets its budget
+            _store.set_fuel(u64::MAX).unwrap();
+        }
+        if WS_UTL.epoch {
+            // no deadline until a call sets one
+            _store.set_epoch_deadline(EPOCH_NEVER);
         }
+        return _store;
     }
 
-    pub fn get_instance(&self, module: &Module) -> Instance {
-        let _ws = WS_STO[self.sn].as_ref().map(|x| x).unwrap();
-        let mut _store = _ws.st.lock().unwrap();
+    pub fn new_store(&self) -> Store<WasmState> {
+        return Self::_new_store(self.sn);
+    }
+
+    // "module::name" of imports the linker can not satisfy
+    pub fn check_imports(&self, _store: &mut Store<WasmState>, module: &Module) -> Vec<String> {
         let mut stc = _store.as_context_mut();
+        let mut unresolved: Vec<String> = Vec::new();
+
+        for imp in module.imports() {
+            let key = format!("{}::{}", imp.module(), imp.name());
+            match (self.lnk.get(&mut stc, imp.module(), imp.name()), imp.ty()) {
+                (None, _) => unresolved.push(key),
+                (Some(Extern::Func(f)), ExternType::Func(want)) => {
+                    let have = f.ty(&stc);
+                    if !have.matches(&want) {
+                        unresolved.push(format!(
+                            "{} --- expected {} --- linker has {}",
+                            key,
+                            get_signature(&want),
+                            get_signature(&have)
+                        ));
+                    }
+                }
+                _ => {}
+            }
+        }
+        return unresolved;
+    }
+
+    pub fn get_instance(
+        &self,
+        _store: &mut Store<WasmState>,
+        wasm_path: &str,
+        module: &Module,
+    ) -> Result<Instance, LoadError> {
+        if let Some(key) = self.check_imports(_store, module).into_iter().next() {
+            return Err(LoadError::UnresolvedImport(wasm_path.to_string(), key));
+        }
 
-        let _instance = self.lnk.instantiate(&mut stc, &module).unwrap();
-        return _instance;
+        let mut stc = _store.as_context_mut();
+        match self.lnk.instantiate(&mut stc, &module) {
+            Ok(_instance) => {
+                return Ok(_instance);
+            }
+            Err(e) => {
+                return Err(LoadError::Instantiate(
+                    wasm_path.to_string(),
+                    format!("{:#}", e),
+                ));
+            }
+        }
     }
 }
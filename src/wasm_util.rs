@@ -1,18 +1,25 @@
+use smcore::smu;
 use smdton::SmDtonBuffer;
 use std::collections::HashMap;
-use std::sync::RwLock;
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock};
+use std::thread;
+use std::time::Duration;
 use wasmtime::*;
 
 use lazy_static::lazy_static;
 
 use crate::wasm::{FL, LOAD_WAY, SZ, WS_JSN};
+use crate::wasm_cache;
+use crate::wasm_config::EngineConfig;
+use crate::wasm_error::LoadError;
 use crate::wasm_import::WasmState;
 
 macro_rules! get_buf_len {
     ($stc: expr, $mem: expr, $poff: expr, $len: ident) => {
         let mut u8a4: [u8; 4] = [0; 4];
         let stc_buf_len = $stc.as_context_mut();
-        $mem.read(stc_buf_len, $poff, &mut u8a4).unwrap();
+        $mem.read(stc_buf_len, $poff, &mut u8a4).ok()?;
         let $len = i32::from_le_bytes(u8a4) as usize;
     };
 }
@@ -21,28 +28,28 @@ macro_rules! get_name_len {
     ($stc: expr, $mem: expr, $poff: expr, $nmlen: ident) => {
         let mut u8b2: [u8; 2] = [0; 2];
         let stc_nmlen = $stc.as_context_mut();
-        $mem.read(stc_nmlen, $poff + SZ::LEN_TY, &mut u8b2).unwrap();
+        $mem.read(stc_nmlen, $poff + SZ::LEN_TY, &mut u8b2).ok()?;
         let $nmlen = u16::from_le_bytes(u8b2) as usize;
     };
 }
 
 macro_rules! get_smb_name {
     ($stc: expr, $mem: expr, $poff: expr, $nmlen: expr, $name: ident) => {
-        let mut vec: Vec<u8> = vec![0; $nmlen - 1];
+        let mut vec: Vec<u8> = vec![0; $nmlen.checked_sub(1)?];
         let piece = &mut vec;
         let stc_name = $stc.as_context_mut();
-        $mem.read(stc_name, $poff + SZ::LEN_TY_NM, piece).unwrap();
-        let $name = String::from_utf8(vec).unwrap();
+        $mem.read(stc_name, $poff + SZ::LEN_TY_NM, piece).ok()?;
+        let $name = String::from_utf8(vec).ok()?;
     };
 }
 
 macro_rules! get_smb {
     ($stc: expr, $mem: expr, $poff: expr, $total: expr, $nmlen: expr, $smb: ident) => {
-        let mut vec: Vec<u8> = vec![0; $total - SZ::NM - $nmlen];
+        let mut vec: Vec<u8> = vec![0; $total.checked_sub(SZ::NM + $nmlen)?];
         let piece: &mut [u8] = &mut vec;
         let stc_smb = $stc.as_context_mut();
         $mem.read(stc_smb, $poff + SZ::LEN_TY_NM + $nmlen, piece)
-            .unwrap();
+            .ok()?;
         let $smb = SmDtonBuffer { off: 0, buf: vec };
     };
 }
@@ -52,82 +59,249 @@ macro_rules! get_buf_txt {
         let mut $vec: Vec<u8> = vec![0; $len];
         let piece: &mut [u8] = &mut $vec;
         let stc_buf_txt = $stc.as_context_mut();
-        $mem.read(stc_buf_txt, $poff + SZ::LEN, piece).unwrap();
+        $mem.read(stc_buf_txt, $poff + SZ::LEN, piece).ok()?;
     };
 }
 
-pub const MAX_STORE: usize = 256;
+// epoch deadline far enough to never trigger
+pub const EPOCH_NEVER: u64 = u64::MAX >> 1;
+
+// built once, by set_engine or else with the defaults on first use
+static WS_ENG: OnceLock<WasmUtil> = OnceLock::new();
 
 lazy_static! {
-    pub static ref WS_UTL: WasmUtil = WasmUtil::new();
+    pub static ref WS_UTL: &'static WasmUtil =
+        WS_ENG.get_or_init(|| WasmUtil::new(&EngineConfig::default()));
     pub static ref WS_SSN: RwLock<usize> = RwLock::new(0);
-    pub static ref WS_MOD: RwLock<HashMap<String, Option<Module>>> = RwLock::new(HashMap::new());
+    pub static ref WS_FRE: RwLock<Vec<usize>> = RwLock::new(Vec::new());
+    pub static ref WS_MOD: RwLock<HashMap<String, Module>> = RwLock::new(HashMap::new());
+}
+
+// false when the engine was already built, egc is then not applied
+pub fn set_engine(egc: &EngineConfig) -> bool {
+    let mut built = false;
+    WS_ENG.get_or_init(|| {
+        built = true;
+        return WasmUtil::new(egc);
+    });
+    return built;
+}
+
+// shared by the running engine and ahead-of-time compiles
+pub fn get_config(egc: &EngineConfig) -> Config {
+    let mut config = Config::new();
+    config.consume_fuel(egc.fuel);
+    config.epoch_interruption(egc.epoch);
+    // the exchange memory need not be the first one
+    config.wasm_multi_memory(true);
+    return config;
+}
+
+// .cwasm for the target triple, None for the host, settings of the running engine
+pub fn precompile(wasm_path: &str, target: Option<&str>) -> Result<Vec<u8>, LoadError> {
+    let mut config = get_config(&WS_UTL.egc);
+    if let Some(t) = target {
+        if let Err(e) = config.target(t) {
+            return Err(LoadError::Compile(
+                wasm_path.to_string(),
+                format!("{:#}", e),
+            ));
+        }
+    }
+    let engine = match Engine::new(&config) {
+        Ok(en) => en,
+        Err(e) => {
+            return Err(LoadError::Compile(
+                wasm_path.to_string(),
+                format!("{:#}", e),
+            ));
+        }
+    };
+
+    let bytes = match std::fs::read(wasm_path) {
+        Ok(b) => b,
+        Err(_) => {
+            return Err(LoadError::NotFound(wasm_path.to_string()));
+        }
+    };
+    match engine.precompile_module(&bytes) {
+        Ok(data) => {
+            return Ok(data);
+        }
+        Err(e) => {
+            return Err(LoadError::Compile(
+                wasm_path.to_string(),
+                format!("{:#}", e),
+            ));
+        }
+    }
 }
 
 pub struct WasmUtil {
     pub engine: Engine,
+    pub egc: EngineConfig,
+    pub fuel: bool,
+    pub epoch: bool,
+    pub tick: Duration,
+    pub cache_dir: Option<PathBuf>,
 }
 
 impl WasmUtil {
-    fn new() -> WasmUtil {
+    fn new(egc: &EngineConfig) -> WasmUtil {
+        let config = get_config(egc);
+        let engine = Engine::new(&config).unwrap();
+        if egc.epoch {
+            let ticker = engine.clone();
+            let tick = egc.tick;
+            thread::spawn(move || {
+                loop {
+                    thread::sleep(tick);
+                    ticker.increment_epoch();
+                }
+            });
+        }
+
         WasmUtil {
-            engine: Engine::default(),
+            engine: engine,
+            egc: egc.clone(),
+            fuel: egc.fuel,
+            epoch: egc.epoch,
+            tick: egc.tick,
+            cache_dir: egc.cache_dir.clone(),
         }
     }
 
-    pub fn load(&self, wasm_path: &str) -> Option<Module> {
-        let _r = Module::from_file(&self.engine, wasm_path);
-        match _r {
-            Ok(_mod) => {
-                return Some(_mod);
+    pub fn get_ticks(&self, deadline: Duration) -> u64 {
+        let tick = self.tick.as_nanos().max(1);
+        let n = deadline.as_nanos().div_ceil(tick);
+        return n.clamp(1, EPOCH_NEVER as u128) as u64;
+    }
+
+    pub fn load(&self, wasm_path: &str) -> Result<Module, LoadError> {
+        if !Path::new(wasm_path).is_file() {
+            return Err(LoadError::NotFound(wasm_path.to_string()));
+        }
+        match Engine::detect_precompiled_file(wasm_path) {
+            Ok(Some(Precompiled::Module)) => {
+                return self.load_precompiled(wasm_path);
+            }
+            Ok(Some(Precompiled::Component)) => {
+                return Err(LoadError::Compile(
+                    wasm_path.to_string(),
+                    "components are not supported".to_string(),
+                ));
             }
             _ => {}
         }
-        return None;
+        if let Some(ref dir) = self.cache_dir {
+            return wasm_cache::load(&self.engine, dir, wasm_path);
+        }
+        match Module::from_file(&self.engine, wasm_path) {
+            Ok(_mod) => {
+                return Ok(_mod);
+            }
+            Err(e) => {
+                return Err(LoadError::Compile(
+                    wasm_path.to_string(),
+                    format!("{:#}", e),
+                ));
+            }
+        }
+    }
+
+    // .cwasm made by precompile, the engine settings must match
+    fn load_precompiled(&self, wasm_path: &str) -> Result<Module, LoadError> {
+        match unsafe { Module::deserialize_file(&self.engine, wasm_path) } {
+            Ok(_mod) => {
+                return Ok(_mod);
+            }
+            Err(e) => {
+                return Err(LoadError::EngineMismatch(
+                    wasm_path.to_string(),
+                    format!("{:#}", e),
+                ));
+            }
+        }
+    }
+
+    pub fn load_bytes(&self, name: &str, bytes: &[u8]) -> Result<Module, LoadError> {
+        match Module::from_binary(&self.engine, bytes) {
+            Ok(_mod) => {
+                return Ok(_mod);
+            }
+            Err(e) => {
+                return Err(LoadError::Compile(name.to_string(), format!("{:#}", e)));
+            }
+        }
+    }
+
+    pub fn load_wat(&self, name: &str, text: &str) -> Result<Module, LoadError> {
+        match Module::new(&self.engine, text) {
+            Ok(_mod) => {
+                return Ok(_mod);
+            }
+            Err(e) => {
+                return Err(LoadError::Compile(name.to_string(), format!("{:#}", e)));
+            }
+        }
+    }
+
+    pub fn add_module(&self, name: &str, _module: Module) {
+        let mut map = WS_MOD.write().unwrap();
+        map.insert(name.to_string(), _module);
     }
 
     pub fn get_ssn(&self) -> usize {
+        {
+            let mut fre = WS_FRE.write().unwrap();
+            if let Some(dsn) = fre.pop() {
+                return dsn;
+            }
+        }
         {
             let mut ssn = WS_SSN.write().unwrap();
             let dsn = *ssn;
-            *ssn = (*ssn + 1) % MAX_STORE;
+            *ssn += 1;
             return dsn;
         }
     }
 
+    pub fn free_ssn(&self, sn: usize) {
+        let mut fre = WS_FRE.write().unwrap();
+        if !fre.contains(&sn) {
+            fre.push(sn);
+        }
+    }
+
     pub fn is_json(&self, sn: usize) -> bool {
         {
             let jsn = WS_JSN.read().unwrap();
-            if jsn[sn] & FL::INJSON == FL::INJSON && LOAD_WAY & FL::INJSON == FL::INJSON {
+            let way = jsn.get(sn).copied().unwrap_or(0);
+            if way & FL::INJSON == FL::INJSON && LOAD_WAY & FL::INJSON == FL::INJSON {
                 return false;
             }
             return true;
         }
     }
 
-    pub fn check_module(&self, wasm_path: &str) -> bool {
+    pub fn check_module(&self, wasm_path: &str) -> Result<(), LoadError> {
         {
             let map = WS_MOD.read().unwrap();
             if map.contains_key(wasm_path) {
-                let itm = map.get(wasm_path).unwrap();
-                if itm.is_some() {
-                    return true;
-                }
-                return false;
+                return Ok(());
             }
         }
-        let m = self.load(wasm_path);
-        {
-            let mut map = WS_MOD.write().unwrap();
-            if m.is_some() {
+        match self.load(wasm_path) {
+            Ok(m) => {
+                let mut map = WS_MOD.write().unwrap();
                 map.insert(wasm_path.to_string(), m);
-                return true;
-            } else {
-                println!("--- load wasm error --- {}", wasm_path);
-                map.insert(wasm_path.to_string(), None);
+                return Ok(());
+            }
+            Err(e) => {
+                smu.log(&format!("--- load wasm error --- {}", e));
+                return Err(e);
             }
         }
-        return false;
     }
 
     pub fn get_buffer_text(
@@ -135,12 +309,12 @@ impl WasmUtil {
         mut stc: StoreContextMut<'_, WasmState>,
         mem: Memory,
         poff: usize,
-    ) -> String {
+    ) -> Option<String> {
         get_buf_len!(stc, mem, poff, len);
         get_buf_txt!(stc, mem, poff, len, vec);
 
-        let txt = String::from_utf8(vec).unwrap();
-        return txt;
+        let txt = String::from_utf8(vec).ok()?;
+        return Some(txt);
     }
 
     pub fn get_buffer_smb(
@@ -148,11 +322,11 @@ impl WasmUtil {
         mut stc: StoreContextMut<'_, WasmState>,
         mem: Memory,
         poff: usize,
-    ) -> (String, SmDtonBuffer) {
+    ) -> Option<(String, SmDtonBuffer)> {
         get_buf_len!(stc, mem, poff, total);
         get_name_len!(stc, mem, poff, nmlen);
         get_smb_name!(stc, mem, poff, nmlen, name);
         get_smb!(stc, mem, poff, total, nmlen, smb);
-        return (name, smb);
+        return Some((name, smb));
     }
 }
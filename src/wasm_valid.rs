@@ -0,0 +1,127 @@
+use json::JsonValue;
+use wasmtime::*;
+
+use crate::wasm_config::WasmConfig;
+use crate::wasm_error::LoadError;
+use crate::wasm_store::WasmStoreStub;
+use crate::wasm_util::WS_UTL;
+
+// plugin abi, name and signature
+const REQUIRED: [(&str, &str); 4] = [
+    ("sminit", "(i32) -> (i32)"),
+    ("smcall", "(i32, i32) -> (i32)"),
+    ("smalloc", "(i32) -> (i32)"),
+    ("smdealloc", "(i32) -> ()"),
+];
+
+#[derive(Clone, Debug)]
+pub struct ExportCheck {
+    pub name: String,
+    pub expected: String,
+    // signature found in the module, None when not exported
+    pub found: Option<String>,
+}
+
+impl ExportCheck {
+    pub fn is_ok(&self) -> bool {
+        return self.found.as_deref() == Some(self.expected.as_str());
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct WasmReport {
+    pub path: String,
+    pub exports: Vec<ExportCheck>,
+    pub memory: bool,
+    // "module::name" of every import
+    pub imports: Vec<String>,
+    // imports the linker has not, or has with another type
+    pub unresolved: Vec<String>,
+}
+
+impl WasmReport {
+    pub fn is_ok(&self) -> bool {
+        return self.memory && self.unresolved.is_empty() && self.exports.iter().all(|x| x.is_ok());
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut jsn = JsonValue::new_object();
+        jsn["path"] = self.path.as_str().into();
+        jsn["ok"] = self.is_ok().into();
+        jsn["memory"] = self.memory.into();
+
+        let mut exp = JsonValue::new_array();
+        for x in &self.exports {
+            let mut itm = JsonValue::new_object();
+            itm["name"] = x.name.as_str().into();
+            itm["expected"] = x.expected.as_str().into();
+            itm["found"] = match x.found {
+                Some(ref f) => f.as_str().into(),
+                None => JsonValue::Null,
+            };
+            itm["ok"] = x.is_ok().into();
+            let _ = exp.push(itm);
+        }
+        jsn["exports"] = exp;
+
+        let mut imp = JsonValue::new_array();
+        for x in &self.imports {
+            let _ = imp.push(x.as_str());
+        }
+        jsn["imports"] = imp;
+
+        let mut unr = JsonValue::new_array();
+        for x in &self.unresolved {
+            let _ = unr.push(x.as_str());
+        }
+        jsn["unresolved"] = unr;
+        return jsn;
+    }
+}
+
+pub fn get_signature(ft: &FuncType) -> String {
+    let params: Vec<String> = ft.params().map(|t| t.to_string()).collect();
+    let results: Vec<String> = ft.results().map(|t| t.to_string()).collect();
+    return format!("({}) -> ({})", params.join(", "), results.join(", "));
+}
+
+// compiles only, nothing is instantiated
+pub fn validate_wasm(wasm_path: &str, cfg: &WasmConfig) -> Result<WasmReport, LoadError> {
+    let _module = WS_UTL.load(wasm_path)?;
+
+    let mut exports: Vec<ExportCheck> = Vec::new();
+    for (name, expected) in REQUIRED {
+        let found = match _module.get_export(name) {
+            Some(ExternType::Func(ft)) => Some(get_signature(&ft)),
+            Some(other) => Some(format!("{:?}", other)),
+            None => None,
+        };
+        exports.push(ExportCheck {
+            name: name.to_string(),
+            expected: expected.to_string(),
+            found: found,
+        });
+    }
+
+    let memory = matches!(_module.get_export(&cfg.memory), Some(ExternType::Memory(_)));
+
+    let imports: Vec<String> = _module
+        .imports()
+        .map(|imp| format!("{}::{}", imp.module(), imp.name()))
+        .collect();
+
+    // a throwaway linker, the same one every slot gets
+    let ws = WasmStoreStub::new(usize::MAX, cfg, &_module);
+    let unresolved = {
+        let mut _store = ws.st.lock().unwrap();
+        ws.check_imports(&mut _store, &_module)
+    };
+
+    return Ok(WasmReport {
+        path: wasm_path.to_string(),
+        exports: exports,
+        memory: memory,
+        imports: imports,
+        unresolved: unresolved,
+    });
+}
@@ -0,0 +1,90 @@
+use std::fs::File;
+use std::io::{PipeReader, PipeWriter, Read};
+use std::thread;
+
+use wasmtime::*;
+use wasmtime_wasi::cli::OutputFile;
+use wasmtime_wasi::p1::{self, WasiP1Ctx};
+use wasmtime_wasi::{DirPerms, FilePerms, WasiCtxBuilder};
+
+use crate::wasm_config::WasmConfig;
+use crate::wasm_error::LoadError;
+use crate::wasm_import::WasmState;
+use crate::wasm_output::WasmOutput;
+
+// real preview1 over the stubs already in the linker
+pub fn add_to_linker(lnk: &mut Linker<WasmState>) {
+    lnk.allow_shadowing(true);
+    p1::add_to_linker_sync(lnk, |s: &mut WasmState| {
+        s.wasi
+            .as_mut()
+            .expect("wasi context set by WasmInstance::init")
+    })
+    .unwrap();
+    lnk.allow_shadowing(false);
+}
+
+// a fresh context for each store, so a restarted instance starts clean
+pub fn get_ctx(wasm_path: &str, sn: usize, cfg: &WasmConfig) -> Result<WasiP1Ctx, LoadError> {
+    let mut b = WasiCtxBuilder::new();
+    b.stdout(get_output(wasm_path, sn, cfg, 1)?);
+    b.stderr(get_output(wasm_path, sn, cfg, 2)?);
+    b.args(&cfg.args);
+    let envs: Vec<(&String, &String)> = cfg.env.iter().collect();
+    b.envs(&envs);
+
+    for po in &cfg.preopens {
+        let (dp, fp) = if po.read_only {
+            (DirPerms::READ, FilePerms::READ)
+        } else {
+            (DirPerms::all(), FilePerms::all())
+        };
+        if let Err(e) = b.preopened_dir(&po.host, &po.guest, dp, fp) {
+            return Err(LoadError::Wasi(
+                wasm_path.to_string(),
+                format!("{} --- {:#}", po.host.display(), e),
+            ));
+        }
+    }
+    return Ok(b.build_p1());
+}
+
+// fd 1 or 2 of the guest, a pipe drained into WasmOutput until the context drops
+fn get_output(
+    wasm_path: &str,
+    sn: usize,
+    cfg: &WasmConfig,
+    fd: usize,
+) -> Result<OutputFile, LoadError> {
+    let err =
+        |e: std::io::Error| LoadError::Wasi(wasm_path.to_string(), format!("fd {} --- {}", fd, e));
+    let (rd, wr) = std::io::pipe().map_err(err)?;
+
+    let mut out = WasmOutput::new(sn);
+    out.set(wasm_path, cfg);
+    thread::Builder::new()
+        .name(format!("smwasm-out-{}-{}", sn, fd))
+        .spawn(move || drain_output(rd, out, fd))
+        .map_err(err)?;
+    return Ok(OutputFile::new(get_file(wr)));
+}
+
+fn drain_output(mut rd: PipeReader, mut out: WasmOutput, fd: usize) {
+    let mut buf: [u8; 4096] = [0; 4096];
+    loop {
+        match rd.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => out.write(fd, &buf[..n]),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn get_file(wr: PipeWriter) -> File {
+    return File::from(std::os::fd::OwnedFd::from(wr));
+}
+
+#[cfg(windows)]
+fn get_file(wr: PipeWriter) -> File {
+    return File::from(std::os::windows::io::OwnedHandle::from(wr));
+}
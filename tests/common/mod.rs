@@ -0,0 +1,150 @@
+#![allow(dead_code)]
+
+use std::sync::Once;
+
+use json::JsonValue;
+use smdton::{SmDtonBuffer, SmDtonBuilder, SmDtonReader};
+use smloadwasm::EngineConfig;
+
+static INIT: Once = Once::new();
+
+// one engine per test binary, with fuel and epochs so limits can be tested
+pub fn setup() {
+    INIT.call_once(|| {
+        let egc = EngineConfig {
+            fuel: true,
+            epoch: true,
+            ..EngineConfig::default()
+        };
+        smloadwasm::init_with(&egc);
+    });
+}
+
+// bytes as the inside of a wat string
+fn get_wat_str(bytes: &[u8]) -> String {
+    return bytes.iter().map(|b| format!("\\{:02x}", b)).collect();
+}
+
+// [len][text], what smcall hands back and hostcallsm takes in json mode
+pub fn get_wat_reply(txt: &str) -> String {
+    let mut v = (txt.len() as u32).to_le_bytes().to_vec();
+    v.extend_from_slice(txt.as_bytes());
+    return get_wat_str(&v);
+}
+
+// REPLY is {"ok":true}, REPLY_B {"ok":false}, BODY runs for every call but smker.get.all
+pub const REPLY: i32 = 4096;
+pub const REPLY_B: i32 = 4352;
+
+// a json mode plugin listing usages, memory:
+//   16 catalog, 1024 literals, 4096 replies, 8192 input,
+//   16384 scratch, 32768 output built by put and copy
+pub fn get_guest(usages: &[&str], imports: &str, body: &str) -> String {
+    let mut cat = JsonValue::new_object();
+    for x in usages {
+        cat[*x] = JsonValue::new_object();
+    }
+    return format!(
+        r#"(module
+  {imports}
+  (memory (export "memory") 1)
+  (global $inlen (mut i32) (i32.const 0))
+  (global $o (mut i32) (i32.const 32772))
+  (data (i32.const 16) "{catalog}")
+  (data (i32.const 1024) "{{\"a\":\"")
+  (data (i32.const 1040) "\",\"e\":\"")
+  (data (i32.const 1056) "\"}}")
+  (data (i32.const 1072) "{{\"t\":\"")
+  (data (i32.const 1088) "\",\"r\":\"")
+  (data (i32.const 1104) "0123456789abcdef")
+  (data (i32.const 4096) "{reply}")
+  (data (i32.const 4352) "{reply_b}")
+  (func (export "sminit") (param i32) (result i32) (i32.const 0))
+  (func (export "smalloc") (param $n i32) (result i32)
+    (global.set $inlen (local.get $n))
+    (i32.const 8192))
+  (func (export "smdealloc") (param i32))
+  ;; "get.all in the input, only the catalog call has it
+  (func $is_catalog (param $p i32) (param $n i32) (result i32)
+    (local $i i32)
+    (block $done
+      (loop $next
+        (br_if $done (i32.gt_u (i32.add (local.get $i) (i32.const 8)) (local.get $n)))
+        (if (i64.eq
+              (i64.and (i64.load (i32.add (local.get $p) (local.get $i))) (i64.const 0x00ffffffffffffff))
+              (i64.const 0x006c6c612e746567))
+          (then (return (i32.const 1))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (i32.const 0))
+  (func $put (param $b i32)
+    (i32.store8 (global.get $o) (local.get $b))
+    (global.set $o (i32.add (global.get $o) (i32.const 1))))
+  (func $copy (param $p i32) (param $n i32)
+    (local $i i32)
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $n)))
+        (call $put (i32.load8_u (i32.add (local.get $p) (local.get $i))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next))))
+  ;; each c string of a pointer table, followed by |
+  (func $list (param $ptrs i32) (param $count i32)
+    (local $i i32) (local $s i32)
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $count)))
+        (local.set $s (i32.load (i32.add (local.get $ptrs) (i32.mul (local.get $i) (i32.const 4)))))
+        (block $end
+          (loop $char
+            (br_if $end (i32.eqz (i32.load8_u (local.get $s))))
+            (call $put (i32.load8_u (local.get $s)))
+            (local.set $s (i32.add (local.get $s) (i32.const 1)))
+            (br $char)))
+        (call $put (i32.const 124))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next))))
+  (func $hex (param $p i32) (param $n i32)
+    (local $i i32) (local $b i32)
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $n)))
+        (local.set $b (i32.load8_u (i32.add (local.get $p) (local.get $i))))
+        (call $put (i32.load8_u (i32.add (i32.const 1104) (i32.shr_u (local.get $b) (i32.const 4)))))
+        (call $put (i32.load8_u (i32.add (i32.const 1104) (i32.and (local.get $b) (i32.const 15)))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next))))
+  ;; the text from 32772 on as a reply at 32768
+  (func $flush (result i32)
+    (i32.store (i32.const 32768) (i32.sub (global.get $o) (i32.const 32772)))
+    (global.set $o (i32.const 32772))
+    (i32.const 32768))
+  (func (export "smcall") (param $p i32) (param $w i32) (result i32)
+    (if (call $is_catalog (i32.add (local.get $p) (i32.const 4)) (global.get $inlen))
+      (then (return (i32.const 16))))
+    {body}))
+"#,
+        imports = imports,
+        catalog = get_wat_reply(&cat.dump()),
+        reply = get_wat_reply("{\"ok\":true}"),
+        reply_b = get_wat_reply("{\"ok\":false}"),
+        body = body,
+    );
+}
+
+pub fn get_input(usage: &str) -> SmDtonBuffer {
+    let mut jsn = JsonValue::new_object();
+    jsn["$usage"] = usage.into();
+    return SmDtonBuilder::new_from_json(&jsn).build();
+}
+
+pub fn get_output(smb: &SmDtonBuffer) -> JsonValue {
+    let rd = SmDtonReader::new(smb.get_buffer());
+    return rd.to_json(1).unwrap_or(JsonValue::Null);
+}
+
+// code of a $error response, empty for a normal reply
+pub fn get_code(smb: &SmDtonBuffer) -> String {
+    let jsn = get_output(smb);
+    return jsn["$error"]["code"].as_str().unwrap_or("").to_string();
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+
+use common::{REPLY, get_guest, get_input, get_output, setup};
+use smloadwasm::LoadError;
+
+fn get_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("smwasm-aot-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    return dir;
+}
+
+fn write_file(name: &str, data: &[u8]) -> String {
+    let path = get_dir().join(name);
+    fs::write(&path, data).unwrap();
+    return path.to_string_lossy().to_string();
+}
+
+#[test]
+fn load_cwasm() {
+    setup();
+    let wat = get_guest(&["aot.good.a"], "", &format!("(i32.const {})", REPLY));
+    let src = write_file("aot.good.wat", wat.as_bytes());
+    let data = smloadwasm::compile_wasm(&src, None).unwrap();
+    let _wp = write_file("aot.good.cwasm", &data);
+
+    smloadwasm::load_wasm(&_wp, 1).unwrap();
+    let out = get_output(&smloadwasm::call_wasm(
+        "aot.good.a",
+        &get_input("aot.good.a"),
+    ));
+    assert_eq!(out["ok"].as_bool(), Some(true));
+    assert!(smloadwasm::unload_wasm(&_wp));
+}
+
+#[test]
+fn engine_mismatch() {
+    setup();
+    // no fuel and no epochs, unlike the engine of this binary
+    let wat = get_guest(&["aot.other.a"], "", &format!("(i32.const {})", REPLY));
+    let engine = wasmtime::Engine::new(&wasmtime::Config::new()).unwrap();
+    let data = engine.precompile_module(wat.as_bytes()).unwrap();
+    let _wp = write_file("aot.other.cwasm", &data);
+
+    let r = smloadwasm::load_wasm(&_wp, 1);
+    assert!(matches!(r, Err(LoadError::EngineMismatch(..))), "{:?}", r);
+    assert!(!smloadwasm::unload_wasm(&_wp));
+}
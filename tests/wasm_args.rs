@@ -0,0 +1,64 @@
+mod common;
+
+use common::{get_guest, get_input, get_output, setup};
+use smloadwasm::WasmConfig;
+
+const IMPORTS: &str = r#"
+  (import "wasi_snapshot_preview1" "args_sizes_get" (func $args_sizes (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "args_get" (func $args_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "environ_sizes_get" (func $env_sizes (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "environ_get" (func $env_get (param i32 i32) (result i32)))"#;
+
+// {"a":"argv|...","e":"environ|..."} read back through the pointer tables
+const BODY: &str = r#"
+    (if (call $args_sizes (i32.const 0) (i32.const 4)) (then (unreachable)))
+    (if (call $args_get (i32.const 16384) (i32.const 20480)) (then (unreachable)))
+    (if (call $env_sizes (i32.const 8) (i32.const 12)) (then (unreachable)))
+    (if (call $env_get (i32.const 24576) (i32.const 28672)) (then (unreachable)))
+    ;; the first string starts at buf, the sizes cover every string with its nul
+    (if (i32.and (i32.ne (i32.load (i32.const 0)) (i32.const 0))
+                 (i32.ne (i32.load (i32.const 16384)) (i32.const 20480)))
+      (then (unreachable)))
+    (if (i32.and (i32.ne (i32.load (i32.const 8)) (i32.const 0))
+                 (i32.ne (i32.load (i32.const 24576)) (i32.const 28672)))
+      (then (unreachable)))
+    (if (i32.load8_u (i32.add (i32.const 20479) (i32.load (i32.const 4)))) (then (unreachable)))
+    (if (i32.load8_u (i32.add (i32.const 28671) (i32.load (i32.const 12)))) (then (unreachable)))
+    (call $copy (i32.const 1024) (i32.const 6))
+    (call $list (i32.const 16384) (i32.load (i32.const 0)))
+    (call $copy (i32.const 1040) (i32.const 7))
+    (call $list (i32.const 24576) (i32.load (i32.const 8)))
+    (call $copy (i32.const 1056) (i32.const 2))
+    (call $flush)"#;
+
+#[test]
+fn argv_and_environ() {
+    setup();
+    let mut cfg = WasmConfig::new(1);
+    cfg.args = vec![
+        "args.wasm".to_string(),
+        "-v".to_string(),
+        "two words".to_string(),
+    ];
+    cfg.env.insert("A_KEY".to_string(), "1".to_string());
+    cfg.env.insert("B_KEY".to_string(), "x=y".to_string());
+    let wat = get_guest(&["args.list.a"], IMPORTS, BODY);
+    smloadwasm::load_wasm_wat_with("args.list", &wat, &cfg).unwrap();
+
+    let out = smloadwasm::call_wasm("args.list.a", &get_input("args.list.a"));
+    let jsn = get_output(&out);
+    assert_eq!(jsn["a"].as_str(), Some("args.wasm|-v|two words|"));
+    assert_eq!(jsn["e"].as_str(), Some("A_KEY=1|B_KEY=x=y|"));
+}
+
+#[test]
+fn empty() {
+    setup();
+    let wat = get_guest(&["args.empty.a"], IMPORTS, BODY);
+    smloadwasm::load_wasm_wat("args.empty", &wat, 1).unwrap();
+
+    let out = smloadwasm::call_wasm("args.empty.a", &get_input("args.empty.a"));
+    let jsn = get_output(&out);
+    assert_eq!(jsn["a"].as_str(), Some(""));
+    assert_eq!(jsn["e"].as_str(), Some(""));
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Once;
+use std::time::SystemTime;
+
+use common::{REPLY, REPLY_B, get_guest, get_input, get_output};
+use smloadwasm::EngineConfig;
+
+static INIT: Once = Once::new();
+
+fn get_dir() -> PathBuf {
+    return std::env::temp_dir().join(format!("smwasm-cache-{}", std::process::id()));
+}
+
+// an engine that caches, one per test binary
+fn setup() {
+    INIT.call_once(|| {
+        let egc = EngineConfig {
+            cache_dir: Some(get_dir()),
+            ..EngineConfig::default()
+        };
+        assert!(smloadwasm::init_with(&egc));
+    });
+}
+
+fn write_guest(name: &str, reply: i32) -> String {
+    let wat = get_guest(
+        &[&format!("{}.a", name)],
+        "",
+        &format!("(i32.const {})", reply),
+    );
+    let path = get_dir().join("src").join(format!("{}.wat", name));
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, wat).unwrap();
+    return path.to_string_lossy().to_string();
+}
+
+// the artifacts of a module and when each was written
+fn get_artifacts(name: &str) -> Vec<(PathBuf, SystemTime)> {
+    let mut v = Vec::new();
+    for ent in fs::read_dir(get_dir()).unwrap().flatten() {
+        let file = ent.file_name().to_string_lossy().to_string();
+        if file.starts_with(&format!("{}.", name)) && file.ends_with(".cwasm") {
+            v.push((ent.path(), ent.metadata().unwrap().modified().unwrap()));
+        }
+    }
+    return v;
+}
+
+fn call_ok(usage: &str) -> Option<bool> {
+    let out = smloadwasm::call_wasm(usage, &get_input(usage));
+    return get_output(&out)["ok"].as_bool();
+}
+
+#[test]
+fn miss_then_hit() {
+    setup();
+    let path = write_guest("cache-hit", REPLY);
+    assert!(get_artifacts("cache-hit").is_empty());
+
+    smloadwasm::load_wasm(&path, 1).unwrap();
+    let first = get_artifacts("cache-hit");
+    assert_eq!(first.len(), 1);
+    assert!(smloadwasm::unload_wasm(&path));
+
+    // read back, not written again
+    smloadwasm::load_wasm(&path, 1).unwrap();
+    assert_eq!(get_artifacts("cache-hit"), first);
+    assert_eq!(call_ok("cache-hit.a"), Some(true));
+    assert!(smloadwasm::unload_wasm(&path));
+}
+
+#[test]
+fn changed_source() {
+    setup();
+    let path = write_guest("cache-change", REPLY);
+    smloadwasm::load_wasm(&path, 1).unwrap();
+    let first = get_artifacts("cache-change");
+    assert!(smloadwasm::unload_wasm(&path));
+
+    // a new artifact, the one of the old bytes is removed
+    write_guest("cache-change", REPLY_B);
+    smloadwasm::load_wasm(&path, 1).unwrap();
+    let second = get_artifacts("cache-change");
+    assert_eq!(second.len(), 1);
+    assert_ne!(second[0].0, first[0].0);
+    assert_eq!(call_ok("cache-change.a"), Some(false));
+    assert!(smloadwasm::unload_wasm(&path));
+}
+
+#[test]
+fn invalidated() {
+    setup();
+    let path = write_guest("cache-bad", REPLY);
+    smloadwasm::load_wasm(&path, 1).unwrap();
+    assert!(smloadwasm::unload_wasm(&path));
+    let first = get_artifacts("cache-bad");
+    fs::write(&first[0].0, b"not an artifact").unwrap();
+
+    // dropped, compiled again and stored again
+    smloadwasm::load_wasm(&path, 1).unwrap();
+    assert_eq!(call_ok("cache-bad.a"), Some(true));
+    let second = get_artifacts("cache-bad");
+    assert_eq!(second.len(), 1);
+    assert_ne!(fs::read(&second[0].0).unwrap(), b"not an artifact");
+    assert!(smloadwasm::unload_wasm(&path));
+}
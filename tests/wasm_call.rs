@@ -0,0 +1,247 @@
+mod common;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{REPLY, get_code, get_guest, get_input, get_output, get_wat_reply, setup};
+use smloadwasm::{CallOption, Deterministic, WasmConfig};
+
+fn get_restarts(path: &str) -> u64 {
+    let st = smloadwasm::get_stats();
+    return st[path]["restarts"].as_u64().unwrap_or(0);
+}
+
+#[test]
+fn reply() {
+    setup();
+    let wat = get_guest(&["call.reply.a"], "", &format!("(i32.const {})", REPLY));
+    smloadwasm::load_wasm_wat("call.reply", &wat, 1).unwrap();
+
+    let out = smloadwasm::call_wasm("call.reply.a", &get_input("call.reply.a"));
+    assert_eq!(get_output(&out)["ok"].as_bool(), Some(true));
+}
+
+#[test]
+fn error_shape() {
+    setup();
+    let wat = get_guest(&["call.shape.a"], "", "(unreachable)");
+    smloadwasm::load_wasm_wat("call.shape", &wat, 1).unwrap();
+
+    let out = smloadwasm::call_wasm("call.shape.a", &get_input("call.shape.a"));
+    let err = &get_output(&out)["$error"];
+    assert_eq!(err["code"].as_str(), Some("trap"));
+    assert!(err["message"].as_str().is_some_and(|m| !m.is_empty()));
+    assert_eq!(err["module"].as_str(), Some("call.shape"));
+    assert_eq!(err["usage"].as_str(), Some("call.shape.a"));
+    assert!(err["backtrace"].is_array());
+
+    let out = smloadwasm::call_wasm("call.shape.none", &get_input("call.shape.none"));
+    assert_eq!(get_code(&out), "unknown_usage");
+}
+
+#[test]
+fn trap_restarts() {
+    setup();
+    let wat = get_guest(&["call.trap.a"], "", "(unreachable)");
+    smloadwasm::load_wasm_wat("call.trap", &wat, 1).unwrap();
+    assert_eq!(get_restarts("call.trap"), 0);
+
+    for n in 1..=2 {
+        let out = smloadwasm::call_wasm("call.trap.a", &get_input("call.trap.a"));
+        assert_eq!(get_code(&out), "trap");
+        assert_eq!(get_restarts("call.trap"), n);
+    }
+}
+
+#[test]
+fn restart_retry() {
+    setup();
+    // sminit and every call trap unless the virtual clock reads 1000ms
+    let check = "(drop (call $clock (i32.const 0) (i64.const 0) (i32.const 16384)))
+    (if (i64.ne (i64.load (i32.const 16384)) (i64.const 1000000000)) (then (unreachable)))";
+    let wat = get_guest(
+        &["call.retry.a"],
+        r#"(import "wasi_snapshot_preview1" "clock_time_get" (func $clock (param i32 i64 i32) (result i32)))"#,
+        &format!("{} (i32.const {})", check, REPLY),
+    )
+    .replace(
+        "(func (export \"sminit\") (param i32) (result i32) (i32.const 0))",
+        &format!(
+            "(func (export \"sminit\") (param i32) (result i32) {} (i32.const 0))",
+            check
+        ),
+    );
+    let mut cfg = WasmConfig::new(1);
+    cfg.deterministic = Some(Deterministic {
+        start_ms: 1000,
+        seed: 1,
+    });
+    smloadwasm::load_wasm_wat_with("call.retry", &wat, &cfg).unwrap();
+
+    // the trap's restart fails, and so does the one the next call tries
+    smloadwasm::set_clock("call.retry", 2000);
+    let out = smloadwasm::call_wasm("call.retry.a", &get_input("call.retry.a"));
+    assert_eq!(get_code(&out), "trap");
+    let out = smloadwasm::call_wasm("call.retry.a", &get_input("call.retry.a"));
+    assert_eq!(get_code(&out), "unavailable");
+    let st = smloadwasm::get_stats();
+    assert_eq!(st["call.retry"]["restart_failed"].as_u64(), Some(2));
+
+    smloadwasm::set_clock("call.retry", 1000);
+    let out = smloadwasm::call_wasm("call.retry.a", &get_input("call.retry.a"));
+    assert_eq!(get_output(&out)["ok"].as_bool(), Some(true));
+    assert_eq!(get_restarts("call.retry"), 1);
+}
+
+#[test]
+fn pool_busy() {
+    setup();
+    // spins until the deadline interrupts it
+    let wat = get_guest(
+        &["call.busy.a"],
+        "",
+        &format!("(loop $spin (br $spin)) (i32.const {})", REPLY),
+    );
+    let mut cfg = WasmConfig::new(1);
+    cfg.pool = 2;
+    cfg.queue = 0;
+    smloadwasm::load_wasm_wat_with("call.busy", &wat, &cfg).unwrap();
+
+    let opt = CallOption {
+        fuel: None,
+        deadline: Some(Duration::from_millis(600)),
+    };
+    let mut hs = Vec::new();
+    for _ in 0..2 {
+        let opt = opt.clone();
+        hs.push(thread::spawn(move || {
+            let out = smloadwasm::call_wasm_with("call.busy.a", &get_input("call.busy.a"), &opt);
+            return get_code(&out);
+        }));
+    }
+    thread::sleep(Duration::from_millis(200));
+
+    // both instances spin and the queue takes nobody
+    let out = smloadwasm::call_wasm_with("call.busy.a", &get_input("call.busy.a"), &opt);
+    assert_eq!(get_code(&out), "busy");
+
+    for h in hs {
+        assert_eq!(h.join().unwrap(), "timeout");
+    }
+}
+
+#[test]
+fn pool_queue() {
+    setup();
+    let wat = get_guest(
+        &["call.queue.a"],
+        "",
+        &format!("(loop $spin (br $spin)) (i32.const {})", REPLY),
+    );
+    let mut cfg = WasmConfig::new(1);
+    cfg.pool = 2;
+    cfg.queue = 1;
+    smloadwasm::load_wasm_wat_with("call.queue", &wat, &cfg).unwrap();
+
+    let opt = CallOption {
+        fuel: None,
+        deadline: Some(Duration::from_millis(300)),
+    };
+    let mut hs = Vec::new();
+    for _ in 0..3 {
+        let opt = opt.clone();
+        hs.push(thread::spawn(move || {
+            let out = smloadwasm::call_wasm_with("call.queue.a", &get_input("call.queue.a"), &opt);
+            return get_code(&out);
+        }));
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    // the third call waits for an instance instead of being refused
+    for h in hs {
+        assert_eq!(h.join().unwrap(), "timeout");
+    }
+}
+
+#[test]
+fn nested_deadline() {
+    setup();
+    // no deadline of its own, only the caller's bounds it
+    let wat = get_guest(
+        &["call.inner.a"],
+        "",
+        &format!("(loop $spin (br $spin)) (i32.const {})", REPLY),
+    );
+    smloadwasm::load_wasm_wat("call.inner", &wat, 1).unwrap();
+
+    let imports = format!(
+        r#"(import "env" "hostcallsm" (func $callsm (param i32) (result i32)))
+  (data (i32.const 2048) "{}")"#,
+        get_wat_reply(r#"{"$usage":"call.inner.a"}"#)
+    );
+    let body = format!(
+        "(drop (call $callsm (i32.const 2048))) (i32.const {})",
+        REPLY
+    );
+    let wat = get_guest(&["call.outer.a"], &imports, &body);
+    smloadwasm::load_wasm_wat("call.outer", &wat, 1).unwrap();
+
+    let opt = CallOption {
+        fuel: None,
+        deadline: Some(Duration::from_millis(300)),
+    };
+    let t0 = Instant::now();
+    smloadwasm::call_wasm_with("call.outer.a", &get_input("call.outer.a"), &opt);
+    assert!(t0.elapsed() < Duration::from_secs(5));
+    let st = smloadwasm::get_stats();
+    assert_eq!(st["call.inner"]["timeout"].as_u64(), Some(1));
+}
+
+#[test]
+fn proc_exit() {
+    setup();
+    let wat = get_guest(
+        &["call.exit.a"],
+        r#"(import "wasi_snapshot_preview1" "proc_exit" (func $exit (param i32)))"#,
+        &format!("(call $exit (i32.const 3)) (i32.const {})", REPLY),
+    );
+    smloadwasm::load_wasm_wat("call.exit", &wat, 1).unwrap();
+
+    let out = smloadwasm::call_wasm("call.exit.a", &get_input("call.exit.a"));
+    let err = &get_output(&out)["$error"];
+    assert_eq!(err["code"].as_str(), Some("exit"));
+    assert!(err["message"].as_str().is_some_and(|m| m.ends_with(" 3")));
+    assert_eq!(get_restarts("call.exit"), 1);
+}
+
+#[test]
+fn abort() {
+    setup();
+    let wat = get_guest(
+        &["call.abort.a"],
+        r#"(import "wasi_snapshot_preview1" "abort" (func $abort))"#,
+        &format!("(call $abort) (i32.const {})", REPLY),
+    );
+    smloadwasm::load_wasm_wat("call.abort", &wat, 1).unwrap();
+
+    let out = smloadwasm::call_wasm("call.abort.a", &get_input("call.abort.a"));
+    assert_eq!(get_code(&out), "abort");
+    assert_eq!(get_restarts("call.abort"), 1);
+}
+
+#[test]
+fn unload_and_load_again() {
+    setup();
+    let wat = get_guest(&["call.unload.a"], "", &format!("(i32.const {})", REPLY));
+    smloadwasm::load_wasm_wat("call.unload", &wat, 1).unwrap();
+    assert!(smloadwasm::unload_wasm("call.unload"));
+    assert!(!smloadwasm::unload_wasm("call.unload"));
+
+    let out = smloadwasm::call_wasm("call.unload.a", &get_input("call.unload.a"));
+    assert_eq!(get_code(&out), "unknown_usage");
+
+    // the freed slot serves the module again
+    smloadwasm::load_wasm_wat("call.unload", &wat, 1).unwrap();
+    let out = smloadwasm::call_wasm("call.unload.a", &get_input("call.unload.a"));
+    assert_eq!(get_output(&out)["ok"].as_bool(), Some(true));
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use common::{REPLY, get_guest};
+
+// a plugin file for the binary, one per test
+fn get_file(name: &str, usages: &[&str]) -> PathBuf {
+    let wat = get_guest(usages, "", &format!("(i32.const {})", REPLY));
+    let path = std::env::temp_dir().join(format!("smwasm-{}-{}.wat", name, std::process::id()));
+    fs::write(&path, wat).unwrap();
+    return path;
+}
+
+fn run(args: &[&str]) -> (i32, String) {
+    let out = Command::new(env!("CARGO_BIN_EXE_smloadwasm"))
+        .args(args)
+        .output()
+        .unwrap();
+    let txt = String::from_utf8(out.stdout).unwrap();
+    return (out.status.code().unwrap_or(-1), txt);
+}
+
+#[test]
+fn run_stdout_is_json() {
+    let path = get_file("cli-run", &["cli.run.a"]);
+    let (code, txt) = run(&["run", path.to_str().unwrap(), "1", "cli.run.a", "{}"]);
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(code, 0, "{}", txt);
+    let jsn = json::parse(&txt).unwrap();
+    assert_eq!(jsn["ok"].as_bool(), Some(true));
+}
+
+#[test]
+fn catalog_json() {
+    let path = get_file("cli-catalog", &["cli.cat.a", "cli.cat.b"]);
+    let (code, txt) = run(&["catalog", path.to_str().unwrap(), "--json"]);
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(code, 0, "{}", txt);
+    let jsn = json::parse(&txt).unwrap();
+    let mut usages: Vec<&str> = jsn.members().filter_map(|x| x["usage"].as_str()).collect();
+    usages.sort();
+    assert_eq!(usages, ["cli.cat.a", "cli.cat.b"]);
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use common::{get_guest, get_input, get_output, setup};
+use smdton::SmDtonBuffer;
+use smloadwasm::{Deterministic, LoadError, WasmConfig};
+
+const IMPORTS: &str = r#"
+  (import "wasi_snapshot_preview1" "clock_time_get" (func $clock (param i32 i64 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "random_get" (func $random (param i32 i32) (result i32)))"#;
+
+// {"t":"clock ns","r":"8 random bytes"}, both in little endian hex
+const BODY: &str = r#"
+    (if (call $clock (i32.const 0) (i64.const 0) (i32.const 16384)) (then (unreachable)))
+    (if (call $random (i32.const 16392) (i32.const 8)) (then (unreachable)))
+    (call $copy (i32.const 1072) (i32.const 6))
+    (call $hex (i32.const 16384) (i32.const 8))
+    (call $copy (i32.const 1088) (i32.const 7))
+    (call $hex (i32.const 16392) (i32.const 8))
+    (call $copy (i32.const 1056) (i32.const 2))
+    (call $flush)"#;
+
+fn get_config() -> WasmConfig {
+    let mut cfg = WasmConfig::new(1);
+    cfg.deterministic = Some(Deterministic {
+        start_ms: 1000,
+        seed: 42,
+    });
+    return cfg;
+}
+
+// load, call, move the clock, call again, unload
+fn run(name: &str, usage: &str, cfg: &WasmConfig) -> Vec<SmDtonBuffer> {
+    let wat = get_guest(&[usage], IMPORTS, BODY);
+    smloadwasm::load_wasm_wat_with(name, &wat, cfg).unwrap();
+
+    let mut outs: Vec<SmDtonBuffer> = Vec::new();
+    for _ in 0..2 {
+        outs.push(smloadwasm::call_wasm(usage, &get_input(usage)));
+        smloadwasm::advance_clock(name, 250);
+    }
+    assert!(smloadwasm::unload_wasm(name));
+    return outs;
+}
+
+#[test]
+fn same_bytes_twice() {
+    setup();
+    let cfg = get_config();
+    let first = run("det.same", "det.same.a", &cfg);
+    let second = run("det.same", "det.same.a", &cfg);
+    for i in 0..2 {
+        assert_eq!(first[i].get_buffer(), second[i].get_buffer());
+    }
+
+    // 1000 ms as ns, then 250 ms later with the generator moved on
+    let (a, b) = (get_output(&first[0]), get_output(&first[1]));
+    assert_eq!(a["t"].as_str(), Some("00ca9a3b00000000"));
+    assert_eq!(b["t"].as_str(), Some("807c814a00000000"));
+    assert_ne!(a["r"], b["r"]);
+}
+
+#[test]
+fn host_random_differs() {
+    setup();
+    let cfg = WasmConfig::new(1);
+    let first = run("det.host", "det.host.a", &cfg);
+    let second = run("det.host", "det.host.a", &cfg);
+    let (a, b) = (get_output(&first[0]), get_output(&second[0]));
+    assert!(a["r"].is_string());
+    assert_ne!(a["r"], b["r"]);
+}
+
+#[test]
+fn refused_with_wasi() {
+    setup();
+    let mut cfg = get_config();
+    cfg.wasi = true;
+    let wat = get_guest(&["det.wasi.a"], IMPORTS, BODY);
+    let r = smloadwasm::load_wasm_wat_with("det.wasi", &wat, &cfg);
+    assert!(matches!(r, Err(LoadError::Wasi(_, _))), "{:?}", r);
+}
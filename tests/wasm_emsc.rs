@@ -0,0 +1,267 @@
+mod common;
+
+use common::{REPLY, get_guest, get_input, get_output, setup};
+use json::JsonValue;
+use smloadwasm::WasmConfig;
+
+// {"t":"resize result","r":"pages after"}
+const HEAP_IMPORTS: &str =
+    r#"(import "env" "emscripten_resize_heap" (func $resize (param i32) (result i32)))"#;
+const HEAP_BODY: &str = r#"
+    (i32.store (i32.const 16384) (call $resize (i32.const 3997696)))
+    (i32.store (i32.const 16388) (memory.size))
+    (call $copy (i32.const 1072) (i32.const 6))
+    (call $hex (i32.const 16384) (i32.const 4))
+    (call $copy (i32.const 1088) (i32.const 7))
+    (call $hex (i32.const 16388) (i32.const 4))
+    (call $copy (i32.const 1056) (i32.const 2))
+    (call $flush)"#;
+
+// 60 pages asking for 61
+fn grow(name: &str, max_page: Option<usize>) -> (String, String) {
+    let usage = format!("{}.a", name);
+    let wat = get_guest(&[&usage], HEAP_IMPORTS, HEAP_BODY).replace(
+        r#"(memory (export "memory") 1)"#,
+        r#"(memory (export "memory") 60)"#,
+    );
+    let mut cfg = WasmConfig::new(1);
+    cfg.max_page = max_page;
+    smloadwasm::load_wasm_wat_with(name, &wat, &cfg).unwrap();
+    let out = get_output(&smloadwasm::call_wasm(&usage, &get_input(&usage)));
+    let t = out["t"].as_str().unwrap_or("").to_string();
+    let r = out["r"].as_str().unwrap_or("").to_string();
+    return (t, r);
+}
+
+#[test]
+fn heap_overgrowth() {
+    setup();
+    // 20% past the old size
+    assert_eq!(
+        grow("emsc.heap", None),
+        ("01000000".into(), "48000000".into())
+    );
+}
+
+#[test]
+fn heap_cut_down() {
+    setup();
+    // 72 and 66 pages are refused, 10% then 5% like emscripten, not 6.7% on the way
+    assert_eq!(
+        grow("emsc.cut", Some(64)),
+        ("01000000".into(), "3f000000".into())
+    );
+}
+
+#[test]
+fn heap_refused() {
+    setup();
+    // even the request alone does not fit
+    assert_eq!(
+        grow("emsc.none", Some(60)),
+        ("00000000".into(), "3c000000".into())
+    );
+}
+
+const TIME: i64 = 1700000000;
+
+#[cfg(unix)]
+fn get_fields() -> [i32; 10] {
+    let tt = TIME as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    assert!(!unsafe { libc::localtime_r(&tt, &mut tm) }.is_null());
+    return [
+        tm.tm_sec,
+        tm.tm_min,
+        tm.tm_hour,
+        tm.tm_mday,
+        tm.tm_mon,
+        tm.tm_year,
+        tm.tm_wday,
+        tm.tm_yday,
+        tm.tm_isdst,
+        tm.tm_gmtoff as i32,
+    ];
+}
+
+// 2023-11-14 22:13:20, a tuesday
+#[cfg(not(unix))]
+fn get_fields() -> [i32; 10] {
+    return [20, 13, 22, 14, 10, 123, 2, 317, 0, 0];
+}
+
+#[test]
+fn localtime_layout() {
+    setup();
+    let imports = r#"(import "env" "_localtime_js" (func $localtime (param i32 i32 i32)))"#;
+    let body = format!(
+        r#"(call $localtime (i32.const {}) (i32.const 0) (i32.const 16384))
+    (call $copy (i32.const 1072) (i32.const 6))
+    (call $hex (i32.const 16384) (i32.const 40))
+    (call $copy (i32.const 1056) (i32.const 2))
+    (call $flush)"#,
+        TIME
+    );
+    let wat = get_guest(&["emsc.time.a"], imports, &body);
+    smloadwasm::load_wasm_wat("emsc.time", &wat, 1).unwrap();
+    let out = get_output(&smloadwasm::call_wasm(
+        "emsc.time.a",
+        &get_input("emsc.time.a"),
+    ));
+
+    // sec, min, hour, mday, mon, year, wday, yday, isdst, gmtoff
+    let f = get_fields();
+    let hex: String = f
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    assert_eq!(out["t"].as_str(), Some(hex.as_str()));
+    // the time of day comes back to utc through gmtoff
+    let day = (f[2] * 3600 + f[1] * 60 + f[0] - f[9]).rem_euclid(86400);
+    assert_eq!(day as i64, TIME % 86400);
+}
+
+// little endian words as the inside of a wat string
+fn get_words(v: &[u32]) -> String {
+    return v
+        .iter()
+        .flat_map(|w| w.to_le_bytes())
+        .map(|b| format!("\\{:02x}", b))
+        .collect();
+}
+
+// itanium abi type_info objects, each [vptr][name] with the vptr at
+// vtable+8 and the type_info of its own class at vtable+4:
+//   20800 St9exception
+//   20816 9MyError, __si_class_type_info over St9exception
+//   20832 5Plain, a class with no bases
+//   20848 7MyMulti, __vmi_class_type_info of 5Plain at 0 and St9exception at 4
+// objects at 20928 (9MyError) and 20944 (7MyMulti) share a vtable whose
+// slot 2 is what(), which answers "boom" only for the right this
+const THROW_FIELDS: &str = r#"
+  (import "env" "__cxa_throw" (func $throw (param i32 i32 i32)))
+  (data (i32.const 20480) "St9exception\00")
+  (data (i32.const 20496) "N10__cxxabiv120__si_class_type_infoE\00")
+  (data (i32.const 20544) "N10__cxxabiv117__class_type_infoE\00")
+  (data (i32.const 20592) "N10__cxxabiv121__vmi_class_type_infoE\00")
+  (data (i32.const 20640) "9MyError\00")
+  (data (i32.const 20656) "5Plain\00")
+  (data (i32.const 20672) "7MyMulti\00")
+  (data (i32.const 20688) "boom\00")
+  (table (export "__indirect_function_table") 3 funcref)
+  (elem (i32.const 0) func $what $what $what)
+  (func $what (param $this i32) (result i32)
+    (if (result i32) (i32.eq (i32.load (local.get $this)) (i32.const 20904))
+      (then (i32.const 20688))
+      (else (i32.const 0))))"#;
+
+fn get_layout() -> String {
+    let words: [(u32, &[u32]); 13] = [
+        // type_info of the type_info classes
+        (20704, &[0, 20544]),
+        (20712, &[0, 20496]),
+        (20720, &[0, 20592]),
+        // their vtables
+        (20736, &[0, 20704, 0]),
+        (20752, &[0, 20712, 0]),
+        (20768, &[0, 20720, 0]),
+        // the thrown types
+        (20800, &[20744, 20480]),
+        (20816, &[20760, 20640, 20800]),
+        (20832, &[20744, 20656]),
+        (20848, &[20776, 20672, 0, 2, 20832, 2, 20800, 4 << 8 | 2]),
+        // vtable of the objects, dtor, dtor, what
+        (20896, &[0, 20816, 0, 1, 2]),
+        // the objects
+        (20928, &[20904]),
+        (20944, &[0, 20904]),
+    ];
+    return words
+        .iter()
+        .map(|(at, v)| format!("\n  (data (i32.const {}) \"{}\")", at, get_words(v)))
+        .collect();
+}
+
+fn throw(name: &str, obj: u32, tinfo: u32) -> JsonValue {
+    let usage = format!("{}.a", name);
+    let fields = format!("{}{}", THROW_FIELDS, get_layout());
+    let body = format!(
+        "(call $throw (i32.const {}) (i32.const {}) (i32.const 0)) (i32.const {})",
+        obj, tinfo, REPLY
+    );
+    let wat = get_guest(&[&usage], &fields, &body);
+    smloadwasm::load_wasm_wat(name, &wat, 1).unwrap();
+    let out = get_output(&smloadwasm::call_wasm(&usage, &get_input(&usage)));
+    return out["$error"].clone();
+}
+
+#[test]
+fn throw_derived() {
+    setup();
+    let err = throw("emsc.throw", 20928, 20816);
+    assert_eq!(err["code"].as_str(), Some("exception"));
+    assert_eq!(err["type"].as_str(), Some("9MyError"));
+    assert_eq!(err["tinfo"].as_i32(), Some(20816));
+    assert_eq!(err["what"].as_str(), Some("boom"));
+}
+
+#[test]
+fn throw_multiple_bases() {
+    setup();
+    // what() gets this moved to the std::exception subobject
+    let err = throw("emsc.multi", 20944, 20848);
+    assert_eq!(err["code"].as_str(), Some("exception"));
+    assert_eq!(err["type"].as_str(), Some("7MyMulti"));
+    assert_eq!(err["what"].as_str(), Some("boom"));
+}
+
+#[test]
+fn throw_not_exception() {
+    setup();
+    // no std::exception base, its vtable is never called
+    let err = throw("emsc.plain", 20928, 20832);
+    assert_eq!(err["code"].as_str(), Some("exception"));
+    assert_eq!(err["type"].as_str(), Some("5Plain"));
+    assert!(err["what"].is_null());
+}
+
+fn load_imports(name: &str, imports: &str) -> Result<(), smloadwasm::LoadError> {
+    let usage = format!("{}.a", name);
+    let wat = get_guest(&[&usage], imports, &format!("(i32.const {})", REPLY));
+    return smloadwasm::load_wasm_wat(name, &wat, 1);
+}
+
+#[test]
+fn env_not_emscripten() {
+    setup();
+    // abort alone says nothing about the toolchain, env stays empty
+    let r = load_imports("emsc.env", r#"(import "env" "abort" (func))"#);
+    match r {
+        Err(smloadwasm::LoadError::UnresolvedImport(_, key)) => assert_eq!(key, "env::abort"),
+        _ => panic!("{:?}", r),
+    }
+}
+
+#[test]
+fn env_emscripten() {
+    setup();
+    let r = load_imports(
+        "emsc.envx",
+        &format!(r#"(import "env" "abort" (func)) {}"#, HEAP_IMPORTS),
+    );
+    r.unwrap();
+    assert!(smloadwasm::unload_wasm("emsc.envx"));
+}
+
+#[test]
+fn env_alias() {
+    setup();
+    // the old names under wasi_snapshot_preview1 still link
+    let r = load_imports(
+        "emsc.alias",
+        &HEAP_IMPORTS.replace(r#""env""#, r#""wasi_snapshot_preview1""#),
+    );
+    r.unwrap();
+    assert!(smloadwasm::unload_wasm("emsc.alias"));
+}
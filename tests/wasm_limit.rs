@@ -0,0 +1,107 @@
+mod common;
+
+use common::{REPLY, get_code, get_guest, get_input, get_output, setup};
+use smloadwasm::{CallOption, WasmConfig};
+
+fn get_stat(path: &str, key: &str) -> u64 {
+    let st = smloadwasm::get_stats();
+    return st[path][key].as_u64().unwrap_or(0);
+}
+
+#[test]
+fn fuel_budget() {
+    setup();
+    let wat = get_guest(
+        &["limit.fuel.a"],
+        "",
+        &format!("(loop $spin (br $spin)) (i32.const {})", REPLY),
+    );
+    let mut cfg = WasmConfig::new(1);
+    cfg.fuel = Some(100_000);
+    smloadwasm::load_wasm_wat_with("limit.fuel", &wat, &cfg).unwrap();
+
+    let out = smloadwasm::call_wasm("limit.fuel.a", &get_input("limit.fuel.a"));
+    assert_eq!(get_code(&out), "out_of_fuel");
+    assert_eq!(get_stat("limit.fuel", "out_of_fuel"), 1);
+}
+
+#[test]
+fn fuel_per_call() {
+    setup();
+    let wat = get_guest(&["limit.call.a"], "", &format!("(i32.const {})", REPLY));
+    smloadwasm::load_wasm_wat("limit.call", &wat, 1).unwrap();
+
+    // too little to even find the usage in the input
+    let opt = CallOption {
+        fuel: Some(10),
+        deadline: None,
+    };
+    let out = smloadwasm::call_wasm_with("limit.call.a", &get_input("limit.call.a"), &opt);
+    assert_eq!(get_code(&out), "out_of_fuel");
+
+    let out = smloadwasm::call_wasm("limit.call.a", &get_input("limit.call.a"));
+    assert_eq!(get_output(&out)["ok"].as_bool(), Some(true));
+    assert!(get_stat("limit.call", "fuel") > 0);
+}
+
+#[test]
+fn max_page() {
+    setup();
+    // {"t":"grow by 10","r":"grow by 2"}, memory.grow results
+    let body = r#"
+    (i32.store (i32.const 16384) (memory.grow (i32.const 10)))
+    (i32.store (i32.const 16388) (memory.grow (i32.const 2)))
+    (call $copy (i32.const 1072) (i32.const 6))
+    (call $hex (i32.const 16384) (i32.const 4))
+    (call $copy (i32.const 1088) (i32.const 7))
+    (call $hex (i32.const 16388) (i32.const 4))
+    (call $copy (i32.const 1056) (i32.const 2))
+    (call $flush)"#;
+    let wat = get_guest(&["limit.page.a"], "", body);
+    let mut cfg = WasmConfig::new(1);
+    cfg.max_page = Some(4);
+    smloadwasm::load_wasm_wat_with("limit.page", &wat, &cfg).unwrap();
+
+    let out = get_output(&smloadwasm::call_wasm(
+        "limit.page.a",
+        &get_input("limit.page.a"),
+    ));
+    assert_eq!(out["t"].as_str(), Some("ffffffff"));
+    assert_eq!(out["r"].as_str(), Some("01000000"));
+    assert_eq!(get_stat("limit.page", "refused"), 1);
+}
+
+#[test]
+fn max_page_at_load() {
+    setup();
+    // the pagenum to grow to at load is over the limit
+    let wat = get_guest(&["limit.load.a"], "", &format!("(i32.const {})", REPLY));
+    let mut cfg = WasmConfig::new(8);
+    cfg.max_page = Some(4);
+    smloadwasm::load_wasm_wat_with("limit.load", &wat, &cfg).unwrap();
+    let out = smloadwasm::call_wasm("limit.load.a", &get_input("limit.load.a"));
+    assert_eq!(get_output(&out)["ok"].as_bool(), Some(true));
+    assert_eq!(get_stat("limit.load", "refused"), 1);
+}
+
+#[test]
+fn max_table() {
+    setup();
+    let body = r#"
+    (i32.store (i32.const 16384) (table.grow $t (ref.null func) (i32.const 10)))
+    (call $copy (i32.const 1072) (i32.const 6))
+    (call $hex (i32.const 16384) (i32.const 4))
+    (call $copy (i32.const 1056) (i32.const 2))
+    (call $flush)"#;
+    let wat = get_guest(&["limit.table.a"], "(table $t 1 funcref)", body);
+    let mut cfg = WasmConfig::new(1);
+    cfg.max_table = Some(4);
+    smloadwasm::load_wasm_wat_with("limit.table", &wat, &cfg).unwrap();
+
+    let out = get_output(&smloadwasm::call_wasm(
+        "limit.table.a",
+        &get_input("limit.table.a"),
+    ));
+    assert_eq!(out["t"].as_str(), Some("ffffffff"));
+    assert_eq!(get_stat("limit.table", "refused"), 1);
+}
@@ -0,0 +1,125 @@
+mod common;
+
+use common::{REPLY, get_guest, get_input, get_output, setup};
+use smloadwasm::LoadError;
+
+#[test]
+fn not_found() {
+    setup();
+    let r = smloadwasm::load_wasm("/nonexistent/load.none.wasm", 1);
+    assert!(matches!(r, Err(LoadError::NotFound(_))), "{:?}", r);
+}
+
+#[test]
+fn compile_error() {
+    setup();
+    let r = smloadwasm::load_wasm_wat("load.compile", "(module (func", 1);
+    assert!(matches!(r, Err(LoadError::Compile(_, _))), "{:?}", r);
+}
+
+#[test]
+fn unresolved_import() {
+    setup();
+    let wat = get_guest(
+        &["load.import.a"],
+        r#"(import "env" "load_none" (func $none))"#,
+        &format!("(call $none) (i32.const {})", REPLY),
+    );
+    let r = smloadwasm::load_wasm_wat("load.import", &wat, 1);
+    match r {
+        Err(LoadError::UnresolvedImport(p, key)) => {
+            assert_eq!(p, "load.import");
+            assert_eq!(key, "env::load_none");
+        }
+        _ => panic!("{:?}", r),
+    }
+}
+
+#[test]
+fn missing_export() {
+    setup();
+    let wat = r#"(module (memory (export "memory") 1)
+        (func (export "sminit") (param i32) (result i32) (i32.const 0)))"#;
+    let r = smloadwasm::load_wasm_wat("load.export", wat, 1);
+    match r {
+        Err(LoadError::MissingExport(_, name)) => assert_eq!(name, "smcall"),
+        _ => panic!("{:?}", r),
+    }
+}
+
+#[test]
+fn init_trap() {
+    setup();
+    let wat = get_guest(&["load.init.a"], "", &format!("(i32.const {})", REPLY)).replace(
+        "(func (export \"sminit\") (param i32) (result i32) (i32.const 0))",
+        "(func (export \"sminit\") (param i32) (result i32) (unreachable))",
+    );
+    let r = smloadwasm::load_wasm_wat("load.init", &wat, 1);
+    assert!(matches!(r, Err(LoadError::InitTrap(_, _))), "{:?}", r);
+}
+
+#[test]
+fn catalog_decode() {
+    setup();
+    // the literal at 1056 is no [len][json] reply
+    let wat = get_guest(&["load.catalog.a"], "", &format!("(i32.const {})", REPLY)).replace(
+        "(then (return (i32.const 16)))",
+        "(then (return (i32.const 1056)))",
+    );
+    let r = smloadwasm::load_wasm_wat("load.catalog", &wat, 1);
+    assert!(matches!(r, Err(LoadError::CatalogDecode(_))), "{:?}", r);
+    // the instance made for it is gone again
+    assert!(!smloadwasm::unload_wasm("load.catalog"));
+    let r = smloadwasm::reload_wasm("load.catalog");
+    assert!(matches!(r, Err(LoadError::NotLoaded(_))), "{:?}", r);
+
+    // and a good module under the same name loads from scratch
+    let wat = get_guest(&["load.catalog.a"], "", &format!("(i32.const {})", REPLY));
+    smloadwasm::load_wasm_wat("load.catalog", &wat, 1).unwrap();
+    assert!(smloadwasm::unload_wasm("load.catalog"));
+}
+
+#[test]
+fn not_loaded() {
+    setup();
+    let r = smloadwasm::reload_wasm("load.never");
+    assert!(matches!(r, Err(LoadError::NotLoaded(_))), "{:?}", r);
+}
+
+#[test]
+fn wasi_without_feature() {
+    setup();
+    let mut cfg = smloadwasm::WasmConfig::new(1);
+    cfg.wasi = true;
+    let wat = get_guest(&["load.wasi.a"], "", &format!("(i32.const {})", REPLY));
+    let r = smloadwasm::load_wasm_wat_with("load.wasi", &wat, &cfg);
+    if cfg!(feature = "wasi") {
+        assert!(r.is_ok(), "{:?}", r);
+    } else {
+        assert!(matches!(r, Err(LoadError::Wasi(_, _))), "{:?}", r);
+    }
+}
+
+#[test]
+fn memory_name() {
+    setup();
+    let wat = get_guest(&["load.memory.a"], "", &format!("(i32.const {})", REPLY)).replace(
+        r#"(memory (export "memory") 1)"#,
+        r#"(memory (export "exch") 1)"#,
+    );
+    let r = smloadwasm::load_wasm_wat("load.memory", &wat, 1);
+    match r {
+        Err(LoadError::MissingExport(_, name)) => assert_eq!(name, "memory"),
+        _ => panic!("{:?}", r),
+    }
+
+    let mut cfg = smloadwasm::WasmConfig::new(1);
+    cfg.memory = "exch".to_string();
+    smloadwasm::load_wasm_wat_with("load.memory", &wat, &cfg).unwrap();
+    let out = get_output(&smloadwasm::call_wasm(
+        "load.memory.a",
+        &get_input("load.memory.a"),
+    ));
+    assert_eq!(out["ok"].as_bool(), Some(true));
+    assert!(smloadwasm::unload_wasm("load.memory"));
+}
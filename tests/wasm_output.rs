@@ -0,0 +1,58 @@
+mod common;
+
+use std::fs;
+
+use common::{REPLY, get_guest, get_input, get_output, setup};
+use smloadwasm::WasmConfig;
+
+#[test]
+fn log_file() {
+    setup();
+    let _lf = std::env::temp_dir().join(format!("smwasm-output-{}.log", std::process::id()));
+    let _ = fs::remove_file(&_lf);
+
+    // "hel" "lo\n" to stdout in two iovecs, "oops\n" to stderr, "tail" left unended
+    let fields = r#"(import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (data (i32.const 20480) "hello\noops\ntail")"#;
+    let body = format!(
+        r#"
+    (i32.store (i32.const 16384) (i32.const 20480))
+    (i32.store (i32.const 16388) (i32.const 3))
+    (i32.store (i32.const 16392) (i32.const 20483))
+    (i32.store (i32.const 16396) (i32.const 3))
+    (drop (call $fd_write (i32.const 1) (i32.const 16384) (i32.const 2) (i32.const 16416)))
+    (i32.store (i32.const 16384) (i32.const 20486))
+    (i32.store (i32.const 16388) (i32.const 5))
+    (drop (call $fd_write (i32.const 2) (i32.const 16384) (i32.const 1) (i32.const 16416)))
+    (i32.store (i32.const 16384) (i32.const 20491))
+    (i32.store (i32.const 16388) (i32.const 4))
+    (drop (call $fd_write (i32.const 1) (i32.const 16384) (i32.const 1) (i32.const 16416)))
+    (i32.const {})"#,
+        REPLY
+    );
+    let wat = get_guest(&["output.log.a"], fields, &body);
+    let mut cfg = WasmConfig::new(1);
+    cfg.log_file = Some(_lf.clone());
+    smloadwasm::load_wasm_wat_with("output.log", &wat, &cfg).unwrap();
+
+    let out = get_output(&smloadwasm::call_wasm(
+        "output.log.a",
+        &get_input("output.log.a"),
+    ));
+    assert_eq!(out["ok"].as_bool(), Some(true));
+    let txt = fs::read_to_string(&_lf).unwrap();
+    let lines: Vec<&str> = txt.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", txt);
+    assert!(lines[0].ends_with(" stdout hello"), "{}", txt);
+    assert!(lines[1].ends_with(" stderr oops"), "{}", txt);
+
+    // the partial line is written when the instance goes
+    assert!(smloadwasm::unload_wasm("output.log"));
+    let txt = fs::read_to_string(&_lf).unwrap();
+    assert!(
+        txt.lines().last().unwrap().ends_with(" stdout tail"),
+        "{}",
+        txt
+    );
+    let _ = fs::remove_file(&_lf);
+}
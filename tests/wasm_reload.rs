@@ -0,0 +1,146 @@
+mod common;
+
+use std::thread;
+use std::time::Duration;
+
+use common::{REPLY, REPLY_B, get_code, get_guest, get_input, get_output, setup};
+use smloadwasm::{CallOption, Deterministic, LoadError, WasmConfig};
+
+fn get_ok(usage: &str) -> Option<bool> {
+    let out = smloadwasm::call_wasm(usage, &get_input(usage));
+    return get_output(&out)["ok"].as_bool();
+}
+
+#[test]
+fn swap() {
+    setup();
+    let wat = get_guest(&["reload.swap.a"], "", &format!("(i32.const {})", REPLY));
+    smloadwasm::load_wasm_wat("reload.swap", &wat, 1).unwrap();
+    assert_eq!(get_ok("reload.swap.a"), Some(true));
+
+    let wat = get_guest(&["reload.swap.a"], "", &format!("(i32.const {})", REPLY_B));
+    smloadwasm::reload_wasm_wat("reload.swap", &wat).unwrap();
+    assert_eq!(get_ok("reload.swap.a"), Some(false));
+    assert!(smloadwasm::unload_wasm("reload.swap"));
+}
+
+#[test]
+fn dropped_usages() {
+    setup();
+    let usages = ["reload.drop.a", "reload.drop.b"];
+    let wat = get_guest(&usages, "", &format!("(i32.const {})", REPLY));
+    smloadwasm::load_wasm_wat("reload.drop", &wat, 1).unwrap();
+    assert_eq!(get_ok("reload.drop.b"), Some(true));
+
+    let wat = get_guest(&usages[..1], "", &format!("(i32.const {})", REPLY_B));
+    smloadwasm::reload_wasm_wat("reload.drop", &wat).unwrap();
+    assert_eq!(get_ok("reload.drop.a"), Some(false));
+    let out = smloadwasm::call_wasm("reload.drop.b", &get_input("reload.drop.b"));
+    assert_eq!(get_code(&out), "unknown_usage");
+}
+
+#[test]
+fn in_flight() {
+    setup();
+    // waits until the virtual clock moves, then answers REPLY
+    let imports = r#"(import "wasi_snapshot_preview1" "clock_time_get" (func $clock (param i32 i64 i32) (result i32)))"#;
+    let body = format!(
+        r#"(drop (call $clock (i32.const 0) (i64.const 0) (i32.const 16384)))
+    (loop $wait
+      (drop (call $clock (i32.const 0) (i64.const 0) (i32.const 16392)))
+      (br_if $wait (i64.eq (i64.load (i32.const 16384)) (i64.load (i32.const 16392)))))
+    (i32.const {})"#,
+        REPLY
+    );
+    let mut cfg = WasmConfig::new(1);
+    cfg.deterministic = Some(Deterministic {
+        start_ms: 1000,
+        seed: 1,
+    });
+    let wat = get_guest(&["reload.flight.a"], imports, &body);
+    smloadwasm::load_wasm_wat_with("reload.flight", &wat, &cfg).unwrap();
+
+    let old = thread::spawn(|| {
+        let opt = CallOption {
+            fuel: None,
+            deadline: Some(Duration::from_secs(20)),
+        };
+        let usage = "reload.flight.a";
+        let out = smloadwasm::call_wasm_with(usage, &get_input(usage), &opt);
+        return get_output(&out)["ok"].as_bool();
+    });
+    thread::sleep(Duration::from_millis(200));
+
+    // the reload swaps at once and then waits for the old call to end
+    let wat = get_guest(
+        &["reload.flight.a"],
+        "",
+        &format!("(i32.const {})", REPLY_B),
+    );
+    let reload = thread::spawn(move || smloadwasm::reload_wasm_wat("reload.flight", &wat));
+    // a call before the swap would queue behind the old one
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(get_ok("reload.flight.a"), Some(false));
+    assert!(!old.is_finished());
+
+    smloadwasm::advance_clock("reload.flight", 1);
+    assert_eq!(old.join().unwrap(), Some(true));
+    reload.join().unwrap().unwrap();
+}
+
+#[test]
+fn in_memory() {
+    setup();
+    let wat = get_guest(&["reload.mem.a"], "", &format!("(i32.const {})", REPLY));
+    smloadwasm::load_wasm_wat("reload.mem", &wat, 1).unwrap();
+    let r = smloadwasm::reload_wasm("reload.mem");
+    assert!(matches!(r, Err(LoadError::InMemory(_))), "{:?}", r);
+
+    let r = smloadwasm::reload_wasm_wat("reload.none", &wat);
+    assert!(matches!(r, Err(LoadError::NotLoaded(_))), "{:?}", r);
+}
+
+// sminit spins a while, so reloads overlap between reading the old slot and the swap
+fn get_slow(reply: i32) -> String {
+    let wat = get_guest(&["reload.race.a"], "", &format!("(i32.const {})", reply));
+    return wat.replace(
+        "(func (export \"sminit\") (param i32) (result i32) (i32.const 0))",
+        r#"(func (export "sminit") (param i32) (result i32) (local $i i32)
+    (loop $spin
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br_if $spin (i32.lt_u (local.get $i) (i32.const 5000000))))
+    (i32.const 0))"#,
+    );
+}
+
+#[test]
+fn concurrent() {
+    setup();
+    let (wat_a, wat_b) = (get_slow(REPLY), get_slow(REPLY_B));
+    smloadwasm::load_wasm_wat("reload.race", &wat_a, 1).unwrap();
+
+    let mut hs = Vec::new();
+    for t in 0..4 {
+        let (wat_a, wat_b) = (wat_a.clone(), wat_b.clone());
+        hs.push(thread::spawn(move || {
+            let mut fails = Vec::new();
+            for n in 0..20 {
+                let wat = if (t + n) % 2 == 0 { &wat_a } else { &wat_b };
+                smloadwasm::reload_wasm_wat("reload.race", wat).unwrap();
+                let usage = "reload.race.a";
+                let out = smloadwasm::call_wasm(usage, &get_input(usage));
+                if get_output(&out)["ok"].as_bool().is_none() {
+                    fails.push(get_code(&out));
+                }
+            }
+            return fails;
+        }));
+    }
+    for h in hs {
+        assert_eq!(h.join().unwrap(), Vec::<String>::new());
+    }
+
+    assert!(smloadwasm::unload_wasm("reload.race"));
+    let out = smloadwasm::call_wasm("reload.race.a", &get_input("reload.race.a"));
+    assert_eq!(get_code(&out), "unknown_usage");
+}
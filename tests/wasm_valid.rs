@@ -0,0 +1,70 @@
+mod common;
+
+use std::fs;
+
+use common::{REPLY, get_guest, setup};
+use smloadwasm::LoadError;
+
+fn write_guest(name: &str, wat: &str) -> String {
+    let dir = std::env::temp_dir().join(format!("smwasm-valid-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!("{}.wat", name));
+    fs::write(&path, wat).unwrap();
+    return path.to_string_lossy().to_string();
+}
+
+#[test]
+fn valid_ok() {
+    setup();
+    let wat = get_guest(&["valid.ok.a"], "", &format!("(i32.const {})", REPLY));
+    let _wp = write_guest("valid.ok", &wat);
+
+    let rpt = smloadwasm::validate_wasm(&_wp).unwrap();
+    assert!(rpt.is_ok(), "{}", rpt.to_json().dump());
+    assert_eq!(rpt.path, _wp);
+    assert!(rpt.memory);
+    assert_eq!(rpt.exports.len(), 4);
+    assert!(rpt.unresolved.is_empty());
+    // nothing was instantiated
+    assert!(!smloadwasm::unload_wasm(&_wp));
+}
+
+#[test]
+fn valid_bad() {
+    setup();
+    // smdealloc not exported, smalloc taking an i64, an import no linker has
+    let wat = get_guest(
+        &["valid.bad.a"],
+        r#"(import "env" "load_none" (func))
+  (func (export "smalloc") (param i64) (result i32) (i32.const 0))"#,
+        &format!("(i32.const {})", REPLY),
+    )
+    .replace(
+        r#"(func (export "smalloc") (param $n i32)"#,
+        "(func (param $n i32)",
+    )
+    .replace(r#"(func (export "smdealloc")"#, "(func");
+    let _wp = write_guest("valid.bad", &wat);
+
+    let rpt = smloadwasm::validate_wasm(&_wp).unwrap();
+    assert!(!rpt.is_ok());
+    let get = |name: &str| rpt.exports.iter().find(|x| x.name == name).unwrap().clone();
+    assert!(get("sminit").is_ok());
+    assert!(get("smcall").is_ok());
+    assert_eq!(get("smalloc").found.as_deref(), Some("(i64) -> (i32)"));
+    assert!(!get("smalloc").is_ok());
+    assert_eq!(get("smdealloc").found, None);
+    assert!(rpt.imports.contains(&"env::load_none".to_string()));
+    assert_eq!(rpt.unresolved, vec!["env::load_none".to_string()]);
+
+    let jsn = rpt.to_json();
+    assert_eq!(jsn["ok"].as_bool(), Some(false));
+    assert!(jsn["exports"][3]["found"].is_null());
+}
+
+#[test]
+fn valid_not_found() {
+    setup();
+    let r = smloadwasm::validate_wasm("/nonexistent/valid.none.wasm");
+    assert!(matches!(r, Err(LoadError::NotFound(_))), "{:?}", r);
+}